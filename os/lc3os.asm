@@ -0,0 +1,700 @@
+; LC-3 operating system bundled with the vm
+;
+; Traps and exceptions are dispatched through the vector tables, and every
+; service routine returns with RTI. The startup code sets up the supervisor
+; stack and drops to user mode at the address kept in USER_PC.
+
+        .ORIG x0000
+
+; Trap vector table, x0000-x00FF
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL TRAP_GETC
+        .FILL TRAP_OUT
+        .FILL TRAP_PUTS
+        .FILL TRAP_IN
+        .FILL TRAP_PUTSP
+        .FILL TRAP_HALT
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+        .FILL BAD_TRAP
+
+; Interrupt vector table, x0100-x01FF. The first entries are the exceptions
+        .FILL EX_PRIVILEGE
+        .FILL EX_ILLEGAL
+        .FILL EX_ACV
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+        .FILL BAD_INT
+
+; Address of the user program, written by the vm before booting (x0200)
+USER_PC .FILL x3000
+
+; Startup code (x0201): push a user mode PSR and the user PC, then RTI to them
+OS_START
+        LD R6, OS_SSP
+        LD R0, USER_PSR
+        ADD R6, R6, #-1
+        STR R0, R6, #0
+        LD R0, USER_PC
+        ADD R6, R6, #-1
+        STR R0, R6, #0
+        RTI
+
+OS_SSP   .FILL x3000
+USER_PSR .FILL x8002
+OS_KBSR  .FILL xFE00
+OS_KBDR  .FILL xFE02
+OS_DSR   .FILL xFE04
+OS_DDR   .FILL xFE06
+OS_MCR   .FILL xFFFE
+MASK_HI  .FILL x7FFF
+LOW_8    .FILL x00FF
+
+; Writes the character in R0 on the display, when it is ready to take it
+OS_PUTC
+        ST R1, PUTC_R1
+PUTC_WAIT
+        LDI R1, OS_DSR
+        BRzp PUTC_WAIT
+        STI R0, OS_DDR
+        LD R1, PUTC_R1
+        RET
+
+; GETC, reads a character into R0 without echoing it
+TRAP_GETC
+        LDI R0, OS_KBSR
+        BRzp TRAP_GETC
+        LDI R0, OS_KBDR
+        RTI
+
+; OUT, writes the character in R0
+TRAP_OUT
+        ST R7, SAVE_R7
+        JSR OS_PUTC
+        LD R7, SAVE_R7
+        RTI
+
+; PUTS, writes the string that starts at R0, one character per word
+TRAP_PUTS
+        ST R7, SAVE_R7
+        ST R0, SAVE_R0
+        ST R1, SAVE_R1
+        ADD R1, R0, #0
+PUTS_LOOP
+        LDR R0, R1, #0
+        BRz PUTS_DONE
+        JSR OS_PUTC
+        ADD R1, R1, #1
+        BRnzp PUTS_LOOP
+PUTS_DONE
+        LD R0, SAVE_R0
+        LD R1, SAVE_R1
+        LD R7, SAVE_R7
+        RTI
+
+; IN, prompts for a character, echoes it and leaves it in R0
+TRAP_IN
+        ST R7, SAVE_R7
+        ST R1, SAVE_R1
+        LEA R1, IN_PROMPT
+IN_PROMPT_LOOP
+        LDR R0, R1, #0
+        BRz IN_READ
+        JSR OS_PUTC
+        ADD R1, R1, #1
+        BRnzp IN_PROMPT_LOOP
+IN_READ
+        LDI R0, OS_KBSR
+        BRzp IN_READ
+        LDI R0, OS_KBDR
+        JSR OS_PUTC
+        LD R1, SAVE_R1
+        LD R7, SAVE_R7
+        RTI
+
+; PUTSP, writes the string that starts at R0, two characters per word
+TRAP_PUTSP
+        ST R7, SAVE_R7
+        ST R0, SAVE_R0
+        ST R1, SAVE_R1
+        ST R2, SAVE_R2
+        ST R3, SAVE_R3
+        ADD R1, R0, #0
+PUTSP_LOOP
+        LDR R2, R1, #0
+        BRz PUTSP_DONE
+        LD R3, LOW_8
+        AND R0, R2, R3
+        JSR OS_PUTC
+        AND R3, R3, #0
+        ADD R3, R3, #8
+PUTSP_SHIFT
+        ADD R2, R2, #0
+        BRzp PUTSP_SHIFT_ZERO
+        ADD R2, R2, R2
+        ADD R2, R2, #1
+        BRnzp PUTSP_SHIFT_NEXT
+PUTSP_SHIFT_ZERO
+        ADD R2, R2, R2
+PUTSP_SHIFT_NEXT
+        ADD R3, R3, #-1
+        BRp PUTSP_SHIFT
+        LD R3, LOW_8
+        AND R0, R2, R3
+        BRz PUTSP_DONE
+        JSR OS_PUTC
+        ADD R1, R1, #1
+        BRnzp PUTSP_LOOP
+PUTSP_DONE
+        LD R0, SAVE_R0
+        LD R1, SAVE_R1
+        LD R2, SAVE_R2
+        LD R3, SAVE_R3
+        LD R7, SAVE_R7
+        RTI
+
+; HALT, writes a message and stops the clock clearing bit 15 of the MCR
+TRAP_HALT
+        LEA R1, HALT_MESSAGE
+STOP_WITH_MESSAGE
+        LDR R0, R1, #0
+        BRz STOP_CLOCK
+        JSR OS_PUTC
+        ADD R1, R1, #1
+        BRnzp STOP_WITH_MESSAGE
+STOP_CLOCK
+        LDI R0, OS_MCR
+        LD R1, MASK_HI
+        AND R0, R0, R1
+        STI R0, OS_MCR
+        BRnzp STOP_CLOCK
+
+; Unknown trap vectors and exceptions write what happened and halt
+BAD_TRAP
+        LEA R1, BAD_TRAP_MESSAGE
+        BRnzp STOP_WITH_MESSAGE
+EX_PRIVILEGE
+        LEA R1, PRIVILEGE_MESSAGE
+        BRnzp STOP_WITH_MESSAGE
+EX_ILLEGAL
+        LEA R1, ILLEGAL_MESSAGE
+        BRnzp STOP_WITH_MESSAGE
+EX_ACV
+        LEA R1, ACV_MESSAGE
+        BRnzp STOP_WITH_MESSAGE
+
+; Interrupts without a service routine are ignored
+BAD_INT
+        RTI
+
+SAVE_R0 .FILL x0000
+SAVE_R1 .FILL x0000
+SAVE_R2 .FILL x0000
+SAVE_R3 .FILL x0000
+SAVE_R7 .FILL x0000
+PUTC_R1 .FILL x0000
+
+IN_PROMPT         .STRINGZ "Enter a character: \n"
+HALT_MESSAGE      .STRINGZ "HALT detected\n"
+BAD_TRAP_MESSAGE  .STRINGZ "Unknown trap vector\n"
+PRIVILEGE_MESSAGE .STRINGZ "Privilege mode violation\n"
+ILLEGAL_MESSAGE   .STRINGZ "Illegal opcode\n"
+ACV_MESSAGE       .STRINGZ "Access control violation\n"
+
+        .END
@@ -0,0 +1,103 @@
+use std::fmt;
+
+/// The part of a line a token takes: the column where it starts, from 1, and its length in characters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub column: usize,
+    pub len: usize,
+}
+
+/// An error in a line, pointing to the part of it that is wrong
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl LineError {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        LineError {
+            span,
+            message: message.into(),
+        }
+    }
+}
+
+/// An error found assembling a source, with the line where it was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: usize,
+    pub span: Span,
+    pub message: String,
+    /// The text of the line, to show the error on it
+    pub source: String,
+    /// Where the line comes from, if it is in the expansion of a macro
+    pub note: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(file: &str, line: usize, source: &str, error: LineError) -> Self {
+        Diagnostic {
+            file: file.to_string(),
+            line,
+            span: error.span,
+            message: error.message,
+            source: source.to_string(),
+            note: None,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    /// Shows the place of the error, the message, and the line with carets under the part that is wrong
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.span.column, self.message
+        )?;
+        writeln!(f, "{}", self.source)?;
+
+        // Tabs are kept, so the carets are aligned however the terminal shows them
+        let indent: String = self
+            .source
+            .chars()
+            .take(self.span.column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        write!(f, "{}{}", indent, "^".repeat(self.span.len.max(1)))?;
+        if let Some(note) = &self.note {
+            write!(f, "\n{}", note)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Diagnostic, LineError, Span};
+
+    #[test]
+    fn test_diagnostic_shows_carets_under_the_span() {
+        let error = LineError::new(Span { column: 13, len: 3 }, "Too big");
+        let diagnostic = Diagnostic::new("a.asm", 4, "\tADD R1, R1, #99", error);
+
+        assert_eq!(
+            "a.asm:4:13: Too big\n\tADD R1, R1, #99\n\t           ^^^",
+            diagnostic.to_string()
+        );
+    }
+
+    #[test]
+    fn test_diagnostic_shows_the_note_after_the_line() {
+        let error = LineError::new(Span { column: 1, len: 3 }, "Too big");
+        let mut diagnostic = Diagnostic::new("a.asm", 2, "ADD", error);
+        diagnostic.note = Some("In the macro 'X' used in a.asm:9".to_string());
+
+        assert_eq!(
+            "a.asm:2:1: Too big\nADD\n^^^\nIn the macro 'X' used in a.asm:9",
+            diagnostic.to_string()
+        );
+    }
+}
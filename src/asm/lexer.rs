@@ -0,0 +1,157 @@
+use super::diagnostic::{LineError, Span};
+
+/// What a token is. Words are everything that is not a string or a comma: operations, labels, registers and numbers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    Word(String),
+    Str(String),
+    Comma,
+}
+
+/// A token and the part of the line it takes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+/// Splits a line of source in tokens. Everything after a ';' out of a string is a comment
+pub fn tokenize(line: &str) -> Result<Vec<Token>, LineError> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let start = i;
+        let kind = match chars[i] {
+            ';' => break,
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ',' => {
+                i += 1;
+                TokenKind::Comma
+            }
+            '"' => {
+                let (s, end) = string(&chars, start)?;
+                i = end;
+                TokenKind::Str(s)
+            }
+            _ => {
+                while i < chars.len()
+                    && !matches!(chars[i], ',' | ';' | '"')
+                    && !chars[i].is_whitespace()
+                {
+                    i += 1;
+                }
+                TokenKind::Word(chars[start..i].iter().collect())
+            }
+        };
+        tokens.push(Token {
+            kind,
+            span: Span {
+                column: start + 1,
+                len: i - start,
+            },
+        });
+    }
+
+    Ok(tokens)
+}
+
+/// Takes the characters of the string that starts with the quote in the given position, replacing the escape sequences. Returns the string and the position after the closing quote
+fn string(chars: &[char], start: usize) -> Result<(String, usize), LineError> {
+    let mut s = String::new();
+    let mut i = start + 1;
+    loop {
+        match chars.get(i) {
+            Some('"') => return Ok((s, i + 1)),
+            Some('\\') => {
+                let c = match chars.get(i + 1) {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('0') => '\0',
+                    Some('\\') => '\\',
+                    Some('"') => '"',
+                    Some(c) => {
+                        return Err(LineError::new(
+                            Span {
+                                column: i + 1,
+                                len: 2,
+                            },
+                            format!("Unknown escape sequence '\\{}'", c),
+                        ))
+                    }
+                    None => break,
+                };
+                s.push(c);
+                i += 2;
+            }
+            Some(c) => {
+                s.push(*c);
+                i += 1;
+            }
+            None => break,
+        }
+    }
+
+    Err(LineError::new(
+        Span {
+            column: start + 1,
+            len: chars.len() - start,
+        },
+        "The string is not closed",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tokenize, Span, Token, TokenKind};
+
+    fn token(kind: TokenKind, column: usize, len: usize) -> Token {
+        Token {
+            kind,
+            span: Span { column, len },
+        }
+    }
+
+    fn word(s: &str, column: usize) -> Token {
+        token(TokenKind::Word(s.to_string()), column, s.len())
+    }
+
+    #[test]
+    fn test_tokenize_splits_words_and_commas() {
+        // The comment is left out
+        let tokens = tokenize("LOOP ADD R1, R1,#-1 ; decrement").unwrap();
+
+        assert_eq!(
+            vec![
+                word("LOOP", 1),
+                word("ADD", 6),
+                word("R1", 10),
+                token(TokenKind::Comma, 12, 1),
+                word("R1", 14),
+                token(TokenKind::Comma, 16, 1),
+                word("#-1", 17),
+            ],
+            tokens
+        );
+    }
+
+    #[test]
+    fn test_tokenize_strings_with_escapes_and_semicolons() {
+        // The span of the string has the quotes
+        let tokens = tokenize(".STRINGZ \"a;b\\n\"").unwrap();
+
+        assert_eq!(token(TokenKind::Str("a;b\n".to_string()), 10, 7), tokens[1]);
+    }
+
+    #[test]
+    fn test_tokenize_unclosed_string_is_an_error() {
+        let error = tokenize(".STRINGZ \"hello").unwrap_err();
+
+        assert_eq!(Span { column: 10, len: 6 }, error.span);
+    }
+}
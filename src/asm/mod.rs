@@ -0,0 +1,851 @@
+pub mod diagnostic;
+pub mod lexer;
+pub mod parser;
+pub mod preprocessor;
+
+use crate::{
+    errors::VmError,
+    hardware::{consts, opcodes},
+    symbols::SymbolTable,
+};
+
+use diagnostic::{Diagnostic, LineError, Span};
+use parser::{branch_flags, parse_line, Operand, Statement, Value};
+use preprocessor::{Preprocessor, SourceLine};
+
+use std::{collections::HashMap, fs, path::Path};
+
+/// The result of assembling a source: the words to place from the origin, and the address of every label
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub origin: u16,
+    pub words: Vec<u16>,
+    pub symbols: SymbolTable,
+    /// Every line of the source, with what it was assembled into
+    pub lines: Vec<ListedLine>,
+}
+
+/// A line of the source, with its address and the words it was assembled into, if it has them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedLine {
+    pub file: String,
+    pub line: usize,
+    /// Where the line comes from, as the listing shows it: the number of the line, after the name of its file if it is not the one assembled, and after a '+' if it comes from a macro
+    pub origin: String,
+    pub source: String,
+    pub address: Option<u16>,
+    pub words: Vec<u16>,
+}
+
+impl Program {
+    /// Returns the object image of the program: the origin followed by the words, all of them big endian
+    pub fn to_image(&self) -> Vec<u8> {
+        std::iter::once(self.origin)
+            .chain(self.words.iter().copied())
+            .flat_map(u16::to_be_bytes)
+            .collect()
+    }
+
+    /// Returns the listing of the program: every line of the source next to its address and the words it was assembled into, in hexadecimal and in binary. Lines with more than one word continue in the rows below
+    pub fn listing(&self) -> String {
+        // The line column is as wide as the longest origin
+        let width = self
+            .lines
+            .iter()
+            .map(|line| line.origin.len())
+            .fold(4, usize::max);
+        let mut text = format!(
+            "Address  Hex   Binary            {:>width$}  Source\n",
+            "Line"
+        );
+        for line in &self.lines {
+            let mut words = line.words.iter();
+            let address = line.address.unwrap_or_default();
+            match words.next() {
+                Some(word) => text.push_str(&format!(
+                    "x{:04X}    {:04X}  {:016b}  {:>width$}  {}\n",
+                    address, word, word, line.origin, line.source
+                )),
+                None => {
+                    // The address, if any, and then the source under its column
+                    let address = line.address.map(|address| format!("x{:04X}", address));
+                    text.push_str(&format!(
+                        "{:<33}{:>width$}  {}\n",
+                        address.unwrap_or_default(),
+                        line.origin,
+                        line.source
+                    ))
+                }
+            }
+            for (word_address, word) in (address..=u16::MAX).skip(1).zip(words) {
+                text.push_str(&format!(
+                    "x{:04X}    {:04X}  {:016b}\n",
+                    word_address, word, word
+                ));
+            }
+        }
+        text
+    }
+}
+
+/// A statement of the source, with the position of its line after the preprocessing and its address
+struct Located {
+    index: usize,
+    address: u16,
+    statement: Statement,
+}
+
+/// Assembles the source file in the given path
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> Result<Program, VmError> {
+    assemble_file_with(path, Preprocessor::new())
+}
+
+/// Assembles the source file in the given path, with a preprocessor that can have names already defined
+pub fn assemble_file_with<P: AsRef<Path>>(
+    path: P,
+    mut preprocessor: Preprocessor,
+) -> Result<Program, VmError> {
+    let path = path.as_ref();
+    let file = path.display().to_string();
+    let source =
+        fs::read_to_string(path).map_err(|e| VmError::IncorrectFileNameError(file.clone(), e))?;
+
+    let (lines, errors) = preprocessor.process_with_errors(&file, &source);
+    assemble_lines(&file, &lines, errors)
+}
+
+/// Assembles a source with a single .ORIG ... .END block
+pub fn assemble(source: &str) -> Result<Program, VmError> {
+    assemble_source("<source>", source)
+}
+
+/// Assembles the source, naming it as the given file in the errors
+pub fn assemble_source(file: &str, source: &str) -> Result<Program, VmError> {
+    let (lines, errors) = Preprocessor::new().process_with_errors(file, source);
+    assemble_lines(file, &lines, errors)
+}
+
+/// Returns the errors sorted by the line they were found in, whatever the pass that found them
+fn sorted(mut diagnostics: Vec<(usize, Diagnostic)>) -> VmError {
+    diagnostics.sort_by_key(|(index, _)| *index);
+    VmError::AssemblyErrors(diagnostics.into_iter().map(|(_, d)| d).collect())
+}
+
+/// Assembles the lines left by the preprocessor, with the errors it found. The first pass gives an address to every label, and the second one encodes the statements. All the errors found are returned, in the order of the lines
+fn assemble_lines(
+    file: &str,
+    source: &[SourceLine],
+    preprocessing: Vec<Diagnostic>,
+) -> Result<Program, VmError> {
+    // Every error goes with the position of its line, to sort them at the end. The ones of the preprocessor go in the first line from the same place
+    let mut diagnostics: Vec<(usize, Diagnostic)> = preprocessing
+        .into_iter()
+        .map(|diagnostic| {
+            let index = source
+                .iter()
+                .position(|line| line.file == diagnostic.file && line.line == diagnostic.line)
+                .unwrap_or(source.len());
+            (index, diagnostic)
+        })
+        .collect();
+    // The errors of the whole program go in the last line, or in the file if there are no lines
+    let at = |index: usize, error: LineError| {
+        let diagnostic = match source.get(index) {
+            Some(line) => line.error(error),
+            None => Diagnostic::new(file, 1, "", error),
+        };
+        (index, diagnostic)
+    };
+
+    let mut origin = None;
+    let mut address: u32 = 0;
+    let mut end = None;
+    let mut symbols = SymbolTable::new();
+    let mut statements = Vec::new();
+
+    for (index, line) in source.iter().enumerate() {
+        let statement = match parse_line(&line.text) {
+            Ok(statement) => statement,
+            Err(error) => {
+                diagnostics.push(at(index, error));
+                continue;
+            }
+        };
+        if statement.label.is_none() && statement.operation.is_none() {
+            continue;
+        }
+
+        let Some(start) = origin else {
+            // Without an origin there are no addresses to give, so nothing else can be checked
+            if statement.operation.as_deref() != Some(".ORIG") || statement.label.is_some() {
+                let span = if statement.label.is_some() {
+                    statement.label_span
+                } else {
+                    statement.operation_span
+                };
+                diagnostics.push(at(
+                    index,
+                    LineError::new(span, "The program has to start with .ORIG"),
+                ));
+                break;
+            }
+            match statement.operands.as_slice() {
+                [Operand {
+                    value: Value::Number(n),
+                    ..
+                }] if (0..=0xFFFF).contains(n) => {
+                    origin = Some(*n as u16);
+                    address = *n as u32;
+                }
+                [operand] => {
+                    diagnostics.push(at(
+                        index,
+                        operand.error(".ORIG takes an address from x0000 to xFFFF"),
+                    ));
+                    break;
+                }
+                _ => {
+                    diagnostics.push(at(
+                        index,
+                        LineError::new(statement.operation_span, ".ORIG takes an address"),
+                    ));
+                    break;
+                }
+            }
+            continue;
+        };
+
+        if let Some(label) = &statement.label {
+            if symbols.address(label).is_some() {
+                diagnostics.push(at(
+                    index,
+                    LineError::new(
+                        statement.label_span,
+                        format!("The label '{}' is already defined", label),
+                    ),
+                ));
+            } else {
+                symbols.insert(label, address as u16);
+            }
+        }
+
+        if statement.operation.as_deref() == Some(".END") {
+            end = Some(index);
+            break;
+        }
+
+        // A statement that can't be sized is not encoded, so its error is reported once
+        let size = match size(&statement) {
+            Ok(size) => size,
+            Err(error) => {
+                diagnostics.push(at(index, error));
+                continue;
+            }
+        };
+        if address as usize <= consts::MEMORY_MAX && (address + size) as usize > consts::MEMORY_MAX
+        {
+            diagnostics.push(at(
+                index,
+                LineError::new(
+                    statement.operation_span,
+                    format!(
+                        "The program goes past the end of the memory, it starts at x{:04X}",
+                        start
+                    ),
+                ),
+            ));
+        }
+        statements.push(Located {
+            index,
+            address: address as u16,
+            statement,
+        });
+        address += size;
+    }
+
+    let last = source.len().saturating_sub(1);
+    match origin {
+        None if diagnostics.is_empty() => diagnostics.push(at(
+            last,
+            LineError::new(Span::default(), "The program has no .ORIG"),
+        )),
+        Some(_) if end.is_none() => diagnostics.push(at(
+            last,
+            LineError::new(Span::default(), "The program has no .END"),
+        )),
+        _ => {}
+    }
+    let Some(origin) = origin else {
+        return Err(sorted(diagnostics));
+    };
+
+    let table: HashMap<&str, u16> = symbols.iter().collect();
+    let mut words = Vec::new();
+    let mut listed = statements.iter().peekable();
+    let mut lines = Vec::new();
+    for (index, line) in source.iter().enumerate() {
+        // The lines of other files have the name of the file before their number
+        let name = if line.file == file {
+            String::new()
+        } else {
+            Path::new(&line.file)
+                .file_name()
+                .map(|name| format!("{}:", name.to_string_lossy()))
+                .unwrap_or_default()
+        };
+        let mut listed_line = ListedLine {
+            file: line.file.clone(),
+            line: line.line,
+            origin: format!(
+                "{}{}{}",
+                if line.expansion.is_some() { "+" } else { "" },
+                name,
+                line.line
+            ),
+            source: line.written.clone().unwrap_or_else(|| line.text.clone()),
+            address: None,
+            words: Vec::new(),
+        };
+
+        if let Some(located) = listed.next_if(|located| located.index == index) {
+            match encode(&located.statement, located.address, &table) {
+                Ok(encoded) => {
+                    words.extend(&encoded);
+                    listed_line.address = Some(located.address);
+                    listed_line.words = encoded;
+                }
+                Err(error) => diagnostics.push(at(index, error)),
+            }
+        }
+        lines.push(listed_line);
+    }
+
+    if !diagnostics.is_empty() {
+        return Err(sorted(diagnostics));
+    }
+
+    Ok(Program {
+        origin,
+        words,
+        symbols,
+        lines,
+    })
+}
+
+/// Returns the number of words the statement takes in memory
+fn size(statement: &Statement) -> Result<u32, LineError> {
+    match statement.operation.as_deref() {
+        None => Ok(0),
+        Some(".ORIG") => Err(LineError::new(
+            statement.operation_span,
+            "There can be only one .ORIG",
+        )),
+        Some(".BLKW") => match statement.operands.as_slice() {
+            [Operand {
+                value: Value::Number(n),
+                ..
+            }] if (1..=0xFFFF).contains(n) => Ok(*n as u32),
+            [operand] => Err(operand.error(".BLKW takes a positive number of words")),
+            _ => Err(LineError::new(
+                statement.operation_span,
+                ".BLKW takes a positive number of words",
+            )),
+        },
+        Some(".STRINGZ") => match statement.operands.as_slice() {
+            [operand @ Operand {
+                value: Value::Str(s),
+                ..
+            }] => Ok(string_words(operand, s)?.len() as u32),
+            [operand] => Err(operand.error(".STRINGZ takes a string")),
+            _ => Err(LineError::new(
+                statement.operation_span,
+                ".STRINGZ takes a string",
+            )),
+        },
+        Some(_) => Ok(1),
+    }
+}
+
+/// Encodes the statement at the given address
+fn encode(
+    statement: &Statement,
+    address: u16,
+    symbols: &HashMap<&str, u16>,
+) -> Result<Vec<u16>, LineError> {
+    let Some(operation) = statement.operation.as_deref() else {
+        return Ok(Vec::new());
+    };
+    let operands = statement.operands.as_slice();
+    let offset = |operand: &Operand, bits: u32| pc_offset(operand, address, bits, symbols);
+    // Wrong number of operands is reported on the operation
+    let usage = |message: &str| LineError::new(statement.operation_span, message);
+
+    let word = match operation {
+        ".FILL" => match operands {
+            [operand] => match &operand.value {
+                Value::Number(n) if (-0x8000..=0xFFFF).contains(n) => *n as u16,
+                Value::Number(n) => {
+                    return Err(operand.error(format!("{} doesn't fit in a word", n)))
+                }
+                Value::Label(label) => lookup(operand, label, symbols)?,
+                _ => return Err(operand.error(".FILL takes a number or a label")),
+            },
+            _ => return Err(usage(".FILL takes a number or a label")),
+        },
+        ".BLKW" => return Ok(vec![0; size(statement)? as usize]),
+        ".STRINGZ" => {
+            let [operand @ Operand {
+                value: Value::Str(s),
+                ..
+            }] = operands
+            else {
+                return Err(usage(".STRINGZ takes a string"));
+            };
+            return string_words(operand, s);
+        }
+        "ADD" | "AND" => {
+            let op = if operation == "ADD" {
+                opcodes::OP_ADD
+            } else {
+                opcodes::OP_AND
+            };
+            match operands {
+                [dr, sr1, last] => {
+                    let third = match last.value {
+                        Value::Register(sr2) => sr2,
+                        _ => 1 << 5 | immediate(last, 5)?,
+                    };
+                    op << 12 | register(dr)? << 9 | register(sr1)? << 6 | third
+                }
+                _ => {
+                    return Err(usage(&format!(
+                        "{} takes two registers and a register or a number",
+                        operation
+                    )))
+                }
+            }
+        }
+        "NOT" => match operands {
+            [dr, sr] => opcodes::OP_NOT << 12 | register(dr)? << 9 | register(sr)? << 6 | 0x3F,
+            _ => return Err(usage("NOT takes two registers")),
+        },
+        "LD" | "LDI" | "LEA" | "ST" | "STI" => {
+            let op = match operation {
+                "LD" => opcodes::OP_LD,
+                "LDI" => opcodes::OP_LDI,
+                "LEA" => opcodes::OP_LEA,
+                "ST" => opcodes::OP_ST,
+                _ => opcodes::OP_STI,
+            };
+            match operands {
+                [r, target] => op << 12 | register(r)? << 9 | offset(target, 9)?,
+                _ => {
+                    return Err(usage(&format!(
+                        "{} takes a register and a label",
+                        operation
+                    )))
+                }
+            }
+        }
+        "LDR" | "STR" => {
+            let op = if operation == "LDR" {
+                opcodes::OP_LDR
+            } else {
+                opcodes::OP_STR
+            };
+            match operands {
+                [r, base, n] => {
+                    op << 12 | register(r)? << 9 | register(base)? << 6 | immediate(n, 6)?
+                }
+                _ => {
+                    return Err(usage(&format!(
+                        "{} takes two registers and an offset",
+                        operation
+                    )))
+                }
+            }
+        }
+        "JMP" => match operands {
+            [base] => opcodes::OP_JMP << 12 | register(base)? << 6,
+            _ => return Err(usage("JMP takes a register")),
+        },
+        "RET" => match operands {
+            [] => opcodes::OP_JMP << 12 | consts::RR7 << 6,
+            _ => return Err(usage("RET takes no operands")),
+        },
+        "JSR" => match operands {
+            [target] => opcodes::OP_JSR << 12 | 1 << 11 | offset(target, 11)?,
+            _ => return Err(usage("JSR takes a label")),
+        },
+        "JSRR" => match operands {
+            [base] => opcodes::OP_JSR << 12 | register(base)? << 6,
+            _ => return Err(usage("JSRR takes a register")),
+        },
+        "RTI" => match operands {
+            [] => opcodes::OP_RTI << 12,
+            _ => return Err(usage("RTI takes no operands")),
+        },
+        "TRAP" => match operands {
+            [operand] => match operand.value {
+                Value::Number(n) if (0..=0xFF).contains(&n) => opcodes::OP_TRAP << 12 | n as u16,
+                _ => return Err(operand.error("TRAP takes a vector from x00 to xFF")),
+            },
+            _ => return Err(usage("TRAP takes a vector from x00 to xFF")),
+        },
+        "GETC" | "OUT" | "PUTS" | "IN" | "PUTSP" | "HALT" => {
+            if !operands.is_empty() {
+                return Err(usage(&format!("{} takes no operands", operation)));
+            }
+            let vector = match operation {
+                "GETC" => opcodes::TRAP_GETC,
+                "OUT" => opcodes::TRAP_OUT,
+                "PUTS" => opcodes::TRAP_PUTS,
+                "IN" => opcodes::TRAP_IN,
+                "PUTSP" => opcodes::TRAP_PUTSP,
+                _ => opcodes::TRAP_HALT,
+            };
+            opcodes::OP_TRAP << 12 | vector
+        }
+        _ => {
+            let flags = branch_flags(operation)
+                .ok_or_else(|| usage(&format!("Unknown operation '{}'", operation)))?;
+            match operands {
+                [target] => opcodes::OP_BR << 12 | flags << 9 | offset(target, 9)?,
+                _ => return Err(usage(&format!("{} takes a label", operation))),
+            }
+        }
+    };
+
+    Ok(vec![word])
+}
+
+fn register(operand: &Operand) -> Result<u16, LineError> {
+    match operand.value {
+        Value::Register(r) => Ok(r),
+        _ => Err(operand.error("Expected a register")),
+    }
+}
+
+fn lookup(operand: &Operand, label: &str, symbols: &HashMap<&str, u16>) -> Result<u16, LineError> {
+    symbols
+        .get(label)
+        .copied()
+        .ok_or_else(|| operand.error(format!("The label '{}' is not defined", label)))
+}
+
+/// Encodes a signed number in the given amount of bits
+fn immediate(operand: &Operand, bits: u32) -> Result<u16, LineError> {
+    let Value::Number(n) = operand.value else {
+        return Err(operand.error("Expected a number"));
+    };
+    fit(n, bits).map_err(|message| operand.error(message))
+}
+
+/// Encodes the distance from the pc (the address after the instruction) to a label. A number is taken as the offset itself
+fn pc_offset(
+    operand: &Operand,
+    address: u16,
+    bits: u32,
+    symbols: &HashMap<&str, u16>,
+) -> Result<u16, LineError> {
+    match &operand.value {
+        Value::Number(n) => fit(*n, bits).map_err(|message| operand.error(message)),
+        Value::Label(label) => {
+            let target = lookup(operand, label, symbols)?;
+            let distance = target as i32 - (address as i32 + 1);
+            fit(distance, bits).map_err(|_| {
+                operand.error(format!(
+                    "The label '{}' is {} words away, too far for a PCoffset{}",
+                    label, distance, bits
+                ))
+            })
+        }
+        _ => Err(operand.error("Expected a label")),
+    }
+}
+
+/// Returns the words of a .STRINGZ: a character in each one, and a zero at the end. Only ASCII characters are taken, since the console prints the low byte of the word
+fn string_words(operand: &Operand, s: &str) -> Result<Vec<u16>, LineError> {
+    match s.chars().find(|c| !c.is_ascii()) {
+        Some(c) => Err(operand.error(format!(
+            "The string has '{}', but only ASCII characters can be used",
+            c
+        ))),
+        None => Ok(s.bytes().map(u16::from).chain([0]).collect()),
+    }
+}
+
+/// Checks that the number can be represented in the given amount of bits, and keeps those bits
+fn fit(n: i32, bits: u32) -> Result<u16, String> {
+    let min = -(1 << (bits - 1));
+    let max = (1 << (bits - 1)) - 1;
+    if n < min || n > max {
+        return Err(format!(
+            "{} doesn't fit in {} bits, it has to be from {} to {}",
+            n, bits, min, max
+        ));
+    }
+    Ok(n as u16 & ((1 << bits) - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assemble;
+    use crate::{errors::VmError, os::OS_IMAGE};
+
+    #[test]
+    fn test_assemble_encodes_every_operation() {
+        let program = assemble(
+            ".ORIG x3000
+             LOOP ADD R1, R2, R3
+             ADD R1, R2, #-1
+             AND R0, R0, #0
+             NOT R4, R5
+             BRnp LOOP
+             JMP R2
+             RET
+             JSR LOOP
+             JSRR R3
+             LD R0, DATA
+             LDI R1, DATA
+             LDR R2, R3, #-2
+             LEA R4, DATA
+             ST R5, DATA
+             STI R6, DATA
+             STR R7, R6, #5
+             TRAP x21
+             RTI
+             HALT
+             DATA .FILL xBEEF
+             .END",
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                0x1283, 0x12BF, 0x5020, 0x997F, 0x0BFB, 0xC080, 0xC1C0, 0x4FF8, 0x40C0, 0x2009,
+                0xA208, 0x64FE, 0xE806, 0x3A05, 0xBC04, 0x7F85, 0xF021, 0x8000, 0xF025, 0xBEEF,
+            ],
+            program.words
+        );
+    }
+
+    #[test]
+    fn test_assemble_directives_and_symbols() {
+        let program = assemble(
+            ".ORIG x4000
+             HI .STRINGZ \"hi\"
+             BUF .BLKW 2
+             .FILL BUF
+             .END",
+        )
+        .unwrap();
+
+        assert_eq!(0x4000, program.origin);
+        assert_eq!(
+            vec![b'h' as u16, b'i' as u16, 0, 0, 0, 0x4003],
+            program.words
+        );
+        assert_eq!(
+            vec![("HI", 0x4000), ("BUF", 0x4003)],
+            program.symbols.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_assemble_gives_the_format_the_loader_reads() {
+        let program = assemble(".ORIG x3000\nHALT\n.END").unwrap();
+
+        assert_eq!(vec![0x30, 0x00, 0xF0, 0x25], program.to_image());
+    }
+
+    #[test]
+    fn test_listing_has_every_line_with_its_words() {
+        // The second word of the string goes in its own row
+        let program =
+            assemble(".ORIG x3000\nLOOP ADD R1, R1, #-1 ; count\nS .STRINGZ \"a\"\n.END").unwrap();
+
+        let listing = program.listing();
+        let rows: Vec<&str> = listing.lines().collect();
+
+        assert_eq!(6, rows.len());
+        assert_eq!("1  .ORIG x3000", rows[1].trim_start());
+        assert_eq!(
+            "x3000    127F  0001001001111111     2  LOOP ADD R1, R1, #-1 ; count",
+            rows[2]
+        );
+        assert_eq!(
+            "x3001    0061  0000000001100001     3  S .STRINGZ \"a\"",
+            rows[3]
+        );
+        assert_eq!("x3002    0000  0000000000000000", rows[4]);
+        assert_eq!("4  .END", rows[5].trim_start());
+    }
+
+    #[test]
+    fn test_assemble_expands_macros_and_conditional_blocks() {
+        let source = ".MACRO CLEAR reg\nAND \\reg, \\reg, #0\n.ENDM\n.ORIG x3000\nSTART CLEAR R2\n.IFDEF DEBUG\nOUT\n.ENDIF\nHALT\n.END";
+        let program = assemble(source).unwrap();
+
+        assert_eq!(vec![0x54A0, 0xF025], program.words);
+        assert_eq!(Some(0x3000), program.symbols.address("START"));
+    }
+
+    #[test]
+    fn test_assemble_error_in_a_macro_points_to_its_body() {
+        let source = ".MACRO PUSH reg\nSTR \\reg, R6, #0\n.ENDM\n.ORIG x3000\nPUSH #3\n.END";
+        let Err(e) = assemble(source) else {
+            panic!("The source was expected to have errors");
+        };
+
+        assert_eq!(
+            "<source>:2:5: Expected a register\nSTR #3, R6, #0\n    ^^\nIn the macro 'PUSH' used in <source>:5\nThe source has 1 error",
+            e.to_string()
+        );
+    }
+
+    #[test]
+    fn test_listing_keeps_the_use_of_a_macro_before_its_expansion() {
+        // The lines of the expansion have a '+' before the line of the body they come from
+        let program =
+            assemble(".MACRO CLEAR r\nAND \\r, \\r, #0\n.ENDM\n.ORIG x3000\nGO CLEAR R1\n.END")
+                .unwrap();
+
+        let listing = program.listing();
+        let rows: Vec<&str> = listing.lines().collect();
+
+        assert_eq!(
+            "x3000                               5  GO CLEAR R1",
+            rows[5]
+        );
+        assert_eq!(
+            "x3000    5260  0101001001100000    +2  AND R1, R1, #0",
+            rows[6]
+        );
+    }
+
+    #[test]
+    fn test_listing_has_the_lines_the_preprocessor_leaves_out() {
+        let source = ".MACRO CLEAR r\nAND \\r, \\r, #0\n.ENDM\n.DEFINE SP R6\n.ORIG x3000\n.IFDEF DEBUG\nOUT\n.ENDIF\nHALT\n.END";
+        let program = assemble(source).unwrap();
+
+        let listing = program.listing();
+        let rows: Vec<&str> = listing.lines().skip(1).collect();
+
+        // Every line of the source, in order, and only the HALT with a word
+        assert_eq!(source.lines().count(), rows.len());
+        for ((number, written), row) in (1..).zip(source.lines()).zip(&rows) {
+            assert!(row.ends_with(&format!("{}  {}", number, written)));
+        }
+        assert!(rows[8].starts_with("x3000    F025"));
+    }
+
+    #[test]
+    fn test_assemble_reports_the_errors_of_the_preprocessor_with_its_own() {
+        assert_eq!(
+            vec![(2, 4, 7), (3, 1, 6)],
+            errors(".ORIG x3000\nBR NOWHERE\n.ENDIF\n.END")
+        );
+    }
+
+    #[test]
+    fn test_assemble_the_bundled_os_gives_the_same_image() {
+        let program = assemble(include_str!("../../os/lc3os.asm")).unwrap();
+
+        assert_eq!(OS_IMAGE, program.to_image().as_slice());
+    }
+
+    /// Returns the errors of a source that doesn't assemble, as (line, column, length)
+    fn errors(source: &str) -> Vec<(usize, usize, usize)> {
+        match assemble(source) {
+            Err(VmError::AssemblyErrors(diagnostics)) => diagnostics
+                .iter()
+                .map(|diagnostic| (diagnostic.line, diagnostic.span.column, diagnostic.span.len))
+                .collect(),
+            _ => panic!("The source was expected to have errors"),
+        }
+    }
+
+    #[test]
+    fn test_assemble_with_undefined_label_is_an_error() {
+        assert_eq!(vec![(2, 4, 7)], errors(".ORIG x3000\nBR NOWHERE\n.END"));
+    }
+
+    #[test]
+    fn test_assemble_with_immediate_out_of_range_is_an_error() {
+        assert_eq!(
+            vec![(2, 13, 3)],
+            errors(".ORIG x3000\nADD R1, R1, #16\n.END")
+        );
+    }
+
+    #[test]
+    fn test_assemble_with_label_out_of_pc_offset_range_is_an_error() {
+        // The label is 300 words after the LD, more than a PCoffset9 reaches
+        assert_eq!(
+            vec![(2, 9, 3)],
+            errors(".ORIG x3000\nLD  R0, FAR\n.BLKW 300\nFAR .FILL 1\n.END")
+        );
+    }
+
+    #[test]
+    fn test_assemble_with_duplicate_label_is_an_error() {
+        assert_eq!(
+            vec![(3, 1, 1)],
+            errors(".ORIG x3000\nA ADD R1, R1, #1\nA ADD R1, R1, #1\n.END")
+        );
+    }
+
+    #[test]
+    fn test_assemble_string_with_characters_out_of_ascii_is_an_error() {
+        assert_eq!(
+            vec![(2, 10, 6), (3, 10, 3)],
+            errors(".ORIG x3000\n.STRINGZ \"caf\u{e9}\"\n.STRINGZ \"\u{1F600}\"\n.END")
+        );
+    }
+
+    #[test]
+    fn test_assemble_reports_all_the_errors() {
+        // Errors in both passes, in the order of the lines
+        assert_eq!(
+            vec![(2, 1, 3), (3, 8, 4), (4, 13, 3)],
+            errors(".ORIG x3000\nMOV R1, R2\nLEA R0 NOPE\nAND R2, R2, #99\n.END")
+        );
+    }
+
+    #[test]
+    fn test_assemble_reports_the_errors_of_the_first_pass_in_their_line() {
+        // The .BLKW is sized in the first pass, but goes after the label found undefined in the second one
+        assert_eq!(
+            vec![(2, 4, 7), (3, 7, 1)],
+            errors(".ORIG x3000\nBR NOWHERE\n.BLKW 0\n.END")
+        );
+    }
+
+    #[test]
+    fn test_assemble_reports_a_statement_that_cant_be_sized_once() {
+        assert_eq!(vec![(2, 7, 1)], errors(".ORIG x3000\n.BLKW 0\n.END"));
+    }
+
+    #[test]
+    fn test_assemble_error_message_shows_the_place() {
+        let Err(e) = assemble(".ORIG x3000\nBR NOWHERE\n.END") else {
+            panic!("The source was expected to have errors");
+        };
+
+        assert_eq!(
+            "<source>:2:4: The label 'NOWHERE' is not defined\nBR NOWHERE\n   ^^^^^^^\nThe source has 1 error",
+            e.to_string()
+        );
+    }
+
+    #[test]
+    fn test_assemble_without_orig_is_an_error() {
+        assert_eq!(vec![(1, 1, 3)], errors("ADD R1, R1, #1\n.END"));
+    }
+
+    #[test]
+    fn test_assemble_without_end_is_an_error() {
+        assert!(assemble(".ORIG x3000\nHALT").is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use super::{
+    diagnostic::{LineError, Span},
+    lexer::{tokenize, TokenKind},
+};
+
+/// The value of an operand of an instruction or a directive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Register(u16),
+    Number(i32),
+    Label(String),
+    Str(String),
+}
+
+/// An operand and the part of the line it takes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Operand {
+    pub value: Value,
+    pub span: Span,
+}
+
+impl Operand {
+    /// Returns an error that points to the operand
+    pub fn error(&self, message: impl Into<String>) -> LineError {
+        LineError::new(self.span, message)
+    }
+}
+
+/// A line of source: a label, an operation (an instruction, a trap alias or a directive) and its operands. Every part can be missing
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Statement {
+    pub label: Option<String>,
+    pub label_span: Span,
+    /// The operation in uppercase
+    pub operation: Option<String>,
+    pub operation_span: Span,
+    pub operands: Vec<Operand>,
+}
+
+const OPERATIONS: [&str; 28] = [
+    "ADD", "AND", "NOT", "BR", "JMP", "RET", "JSR", "JSRR", "LD", "LDI", "LDR", "LEA", "ST", "STI",
+    "STR", "TRAP", "RTI", "GETC", "OUT", "PUTS", "IN", "PUTSP", "HALT", ".ORIG", ".END", ".FILL",
+    ".BLKW", ".STRINGZ",
+];
+
+/// Returns true if the word is an operation, ignoring the case. BR can have any of the n, z and p flags, in that order
+pub fn is_operation(word: &str) -> bool {
+    let word = word.to_uppercase();
+    OPERATIONS.contains(&word.as_str()) || branch_flags(&word).is_some()
+}
+
+/// Returns the n, z and p bits of a branch operation (BRn, BRzp...). A BR without flags branches always
+pub fn branch_flags(operation: &str) -> Option<u16> {
+    let flags = operation.strip_prefix("BR")?;
+    if flags.is_empty() {
+        return Some(0b111);
+    }
+
+    let mut bits = 0;
+    let mut rest = flags;
+    for (flag, bit) in [('N', 0b100), ('Z', 0b010), ('P', 0b001)] {
+        if let Some(r) = rest.strip_prefix(flag) {
+            bits |= bit;
+            rest = r;
+        }
+    }
+    if rest.is_empty() {
+        Some(bits)
+    } else {
+        None
+    }
+}
+
+/// Returns true if the word is a branch with the n, z and p flags out of order or repeated, like BRzn
+fn is_misordered_branch(word: &str) -> bool {
+    let word = word.to_uppercase();
+    match word.strip_prefix("BR") {
+        Some(flags) => {
+            !flags.is_empty()
+                && flags.chars().all(|c| matches!(c, 'N' | 'Z' | 'P'))
+                && branch_flags(&word).is_none()
+        }
+        None => false,
+    }
+}
+
+fn branch_order_error(word: &str, span: Span) -> LineError {
+    LineError::new(
+        span,
+        format!(
+            "Invalid branch condition order in '{}', the flags go in the order n, z, p",
+            word
+        ),
+    )
+}
+
+/// Parses a number in hexadecimal (x1F or 0x1F), binary (b101) or decimal (#-3 or -3)
+pub fn parse_number(word: &str) -> Option<i32> {
+    let (negative, digits) = match word.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, word),
+    };
+
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix('x'))
+        .or_else(|| digits.strip_prefix('X'))
+    {
+        let (negative, hex) = match hex.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, hex),
+        };
+        let value = i32::from_str_radix(hex, 16).ok()?;
+        if negative {
+            -value
+        } else {
+            value
+        }
+    } else if let Some(binary) = digits
+        .strip_prefix('b')
+        .or_else(|| digits.strip_prefix('B'))
+    {
+        i32::from_str_radix(binary, 2).ok()?
+    } else {
+        digits
+            .strip_prefix('#')
+            .unwrap_or(digits)
+            .parse::<i32>()
+            .ok()?
+    };
+
+    if negative {
+        Some(-value)
+    } else {
+        Some(value)
+    }
+}
+
+fn value(word: &str) -> Value {
+    let upper = word.to_uppercase();
+    if let Some(number) = upper.strip_prefix('R') {
+        if let Ok(register) = number.parse::<u16>() {
+            if register < 8 && number.len() == 1 {
+                return Value::Register(register);
+            }
+        }
+    }
+
+    match parse_number(word) {
+        Some(number) => Value::Number(number),
+        None => Value::Label(word.to_string()),
+    }
+}
+
+/// Parses a line of source
+pub fn parse_line(line: &str) -> Result<Statement, LineError> {
+    let mut tokens = tokenize(line)?.into_iter().peekable();
+    let mut statement = Statement::default();
+
+    if let Some(token) = tokens.peek() {
+        if let TokenKind::Word(word) = &token.kind {
+            if !is_operation(word) {
+                let label = word.strip_suffix(':').unwrap_or(word);
+                if is_misordered_branch(label) {
+                    return Err(branch_order_error(label, token.span));
+                }
+                // A label that reads as a register or a number would be taken as one in the operands
+                match value(label) {
+                    Value::Label(_) => {}
+                    Value::Register(_) => {
+                        return Err(LineError::new(
+                            token.span,
+                            format!("'{}' is a register, it can't be a label", label),
+                        ))
+                    }
+                    _ => {
+                        return Err(LineError::new(
+                            token.span,
+                            format!("'{}' reads as a number, it can't be a label", label),
+                        ))
+                    }
+                }
+                statement.label = Some(label.to_string());
+                statement.label_span = token.span;
+                tokens.next();
+            }
+        }
+    }
+
+    match tokens.next() {
+        Some(token) => match token.kind {
+            TokenKind::Word(word) if is_operation(&word) => {
+                statement.operation = Some(word.to_uppercase());
+                statement.operation_span = token.span;
+            }
+            TokenKind::Word(word) if is_misordered_branch(&word) => {
+                return Err(branch_order_error(&word, token.span))
+            }
+            // A register or a number after the label means that the label was a misspelled operation
+            _ if statement.label.is_some()
+                && !matches!(token.kind, TokenKind::Word(ref word) if matches!(value(word), Value::Label(_))) =>
+            {
+                return Err(LineError::new(
+                    statement.label_span,
+                    format!(
+                        "Unknown operation '{}'",
+                        statement.label.unwrap_or_default()
+                    ),
+                ))
+            }
+            TokenKind::Word(word) => {
+                return Err(LineError::new(
+                    token.span,
+                    format!("Unknown operation '{}'", word),
+                ))
+            }
+            _ => return Err(LineError::new(token.span, "Expected an operation")),
+        },
+        None => return Ok(statement),
+    }
+
+    // The operands can be separated by commas or just by spaces
+    for token in tokens {
+        let value = match token.kind {
+            TokenKind::Word(word) => value(&word),
+            TokenKind::Str(s) => Value::Str(s),
+            TokenKind::Comma => continue,
+        };
+        statement.operands.push(Operand {
+            value,
+            span: token.span,
+        });
+    }
+
+    Ok(statement)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{branch_flags, parse_line, parse_number, Span, Value};
+
+    #[test]
+    fn test_parse_number_in_every_base() {
+        assert_eq!(Some(0x3000), parse_number("x3000"));
+        assert_eq!(Some(0x3000), parse_number("0x3000"));
+        assert_eq!(Some(-1), parse_number("#-1"));
+        assert_eq!(Some(12), parse_number("12"));
+        assert_eq!(Some(5), parse_number("b101"));
+        assert_eq!(None, parse_number("LOOP"));
+    }
+
+    #[test]
+    fn test_branch_flags_follow_the_nzp_order() {
+        assert_eq!(Some(0b111), branch_flags("BR"));
+        assert_eq!(Some(0b101), branch_flags("BRNP"));
+        assert_eq!(Some(0b111), branch_flags("BRNZP"));
+        assert_eq!(None, branch_flags("BRPN"));
+    }
+
+    #[test]
+    fn test_parse_line_with_label_operation_and_operands() {
+        let statement = parse_line("LOOP add R1, R1, #-1").unwrap();
+
+        assert_eq!(Some("LOOP".to_string()), statement.label);
+        assert_eq!(Some("ADD".to_string()), statement.operation);
+        assert_eq!(
+            vec![Value::Register(1), Value::Register(1), Value::Number(-1)],
+            statement
+                .operands
+                .iter()
+                .map(|operand| operand.value.clone())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Span { column: 18, len: 3 }, statement.operands[2].span);
+    }
+
+    #[test]
+    fn test_parse_line_with_only_a_label() {
+        let statement = parse_line("DONE: ; nothing else").unwrap();
+
+        assert_eq!(Some("DONE".to_string()), statement.label);
+        assert_eq!(None, statement.operation);
+    }
+
+    #[test]
+    fn test_parse_line_with_label_that_reads_as_a_number_is_an_error() {
+        // B1 is binary and x1 and X10 are hexadecimal, so as operands they would be numbers
+        for label in ["B1", "x1", "X10:"] {
+            let error = parse_line(&format!("{} ADD R1, R1, #1", label)).unwrap_err();
+
+            assert_eq!(
+                Span {
+                    column: 1,
+                    len: label.len()
+                },
+                error.span
+            );
+            assert!(error.message.contains("reads as a number"));
+        }
+    }
+
+    #[test]
+    fn test_parse_line_with_branch_flags_out_of_order_is_an_error() {
+        let error = parse_line("BRzn LOOP").unwrap_err();
+
+        assert_eq!(Span { column: 1, len: 4 }, error.span);
+        assert_eq!(
+            "Invalid branch condition order in 'BRzn', the flags go in the order n, z, p",
+            error.message
+        );
+
+        // After a label too
+        let error = parse_line("AGAIN BRpz LOOP").unwrap_err();
+        assert_eq!(Span { column: 7, len: 4 }, error.span);
+    }
+
+    #[test]
+    fn test_parse_line_with_unknown_operation_is_an_error() {
+        let error = parse_line("LOOP MOV R1, R2").unwrap_err();
+
+        assert_eq!(Span { column: 6, len: 3 }, error.span);
+    }
+
+    #[test]
+    fn test_parse_line_with_unknown_operation_and_no_label_points_to_the_operation() {
+        // MOV is not taken as a label, because a register follows it
+        let error = parse_line("MOV R1, R2").unwrap_err();
+
+        assert_eq!(Span { column: 1, len: 3 }, error.span);
+        assert_eq!("Unknown operation 'MOV'", error.message);
+    }
+}
@@ -0,0 +1,832 @@
+use super::{
+    diagnostic::{Diagnostic, LineError, Span},
+    lexer::{tokenize, Token, TokenKind},
+    parser::is_operation,
+};
+use crate::errors::VmError;
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// How many macro expansions can be inside one another, to stop the macros that use themselves
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+/// A line of source after the preprocessing, with the file and the line it comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub file: String,
+    pub line: usize,
+    pub text: String,
+    /// The macro expansions the line comes from, if any
+    pub expansion: Option<String>,
+    /// The line as it was written, to show it in the listing, when the assembler gets something else: a line that uses a macro keeps only its label, and the directives of the preprocessor, the macro definitions and the skipped lines nothing. The lines of the expansion or of the included file follow it
+    pub written: Option<String>,
+}
+
+impl SourceLine {
+    /// Returns the line with nothing for the assembler, just to be shown in the listing
+    fn listed_only(self) -> Self {
+        SourceLine {
+            written: Some(self.text),
+            text: String::new(),
+            ..self
+        }
+    }
+
+    /// Returns a diagnostic that points to the error in this line
+    pub fn error(&self, error: LineError) -> Diagnostic {
+        let mut diagnostic = Diagnostic::new(&self.file, self.line, &self.text, error);
+        diagnostic.note = self.expansion.clone();
+        diagnostic
+    }
+}
+
+/// Splits a source in lines, numbered from 1
+pub fn source_lines(file: &str, source: &str) -> Vec<SourceLine> {
+    source
+        .lines()
+        .enumerate()
+        .map(|(index, text)| SourceLine {
+            file: file.to_string(),
+            line: index + 1,
+            text: text.to_string(),
+            expansion: None,
+            written: None,
+        })
+        .collect()
+}
+
+/// A macro: its parameters and the lines of its body, as they were written
+struct Macro {
+    params: Vec<String>,
+    body: Vec<SourceLine>,
+}
+
+/// A macro whose body is being read. The name is missing if the .MACRO line is wrong, so the body is skipped
+struct Definition {
+    name: Option<String>,
+    start: SourceLine,
+    span: Span,
+    body: Vec<SourceLine>,
+    params: Vec<String>,
+}
+
+/// An .IFDEF or .IFNDEF block that is open
+struct Conditional {
+    start: SourceLine,
+    span: Span,
+    active: bool,
+    has_else: bool,
+}
+
+/// Expands the macros, the included files and the conditional blocks of a source, leaving the lines for the assembler
+#[derive(Default)]
+pub struct Preprocessor {
+    defines: HashMap<String, String>,
+    /// The macros by name, in uppercase
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    /// The files being read, to find the ones that include themselves
+    files: Vec<PathBuf>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defines a name as if the source started with a .DEFINE
+    pub fn define(&mut self, name: &str, value: &str) {
+        self.defines.insert(name.to_string(), value.to_string());
+    }
+
+    /// Preprocesses the source, naming it as the given file. The included files are looked for from the directory of the file that includes them. All the errors found are returned, not just the first one
+    pub fn process(&mut self, file: &str, source: &str) -> Result<Vec<SourceLine>, VmError> {
+        let (lines, diagnostics) = self.process_with_errors(file, source);
+        if diagnostics.is_empty() {
+            Ok(lines)
+        } else {
+            Err(VmError::AssemblyErrors(diagnostics))
+        }
+    }
+
+    /// Like process, but gives the lines even if there are errors, so the assembler can look for its own errors in them
+    pub fn process_with_errors(
+        &mut self,
+        file: &str,
+        source: &str,
+    ) -> (Vec<SourceLine>, Vec<Diagnostic>) {
+        self.files.push(canonical(Path::new(file)));
+        let lines = self.expand(source_lines(file, source), 0);
+        self.files.pop();
+        (lines, std::mem::take(&mut self.diagnostics))
+    }
+
+    /// Preprocesses a sequence of lines: a file or the body of a macro. The conditional blocks and the macro definitions have to be closed in the same sequence
+    fn expand(&mut self, lines: Vec<SourceLine>, depth: usize) -> Vec<SourceLine> {
+        let mut output = Vec::new();
+        let mut conditionals: Vec<Conditional> = Vec::new();
+        let mut definition: Option<Definition> = None;
+
+        for line in lines {
+            // A line that can't be tokenized goes as it is, and the assembler reports it
+            let tokens = tokenize(&line.text).unwrap_or_default();
+            let directive = match tokens.first() {
+                Some(Token {
+                    kind: TokenKind::Word(word),
+                    span,
+                }) if word.starts_with('.') => Some((word.to_uppercase(), *span)),
+                _ => None,
+            };
+            let directive = directive
+                .as_ref()
+                .map(|(directive, span)| (directive.as_str(), *span));
+
+            // The body of a macro is kept as it is, to expand it when the macro is used
+            if let Some(current) = &mut definition {
+                match directive {
+                    Some((".ENDM", _)) => {
+                        if let Some(current) = definition.take() {
+                            self.end_macro(current);
+                        }
+                    }
+                    Some((".MACRO", span)) => self.diagnostics.push(line.error(LineError::new(
+                        span,
+                        "A macro can't be defined inside another one",
+                    ))),
+                    _ => current.body.push(line.clone()),
+                }
+                output.push(line.listed_only());
+                continue;
+            }
+
+            let active = conditionals.iter().all(|conditional| conditional.active);
+            match directive {
+                Some((directive @ (".IFDEF" | ".IFNDEF"), span)) => {
+                    // The blocks inside a skipped one are skipped too, whatever their condition
+                    let condition = active
+                        && match name_operand(directive, &tokens, span) {
+                            Ok(name) => self.defines.contains_key(name) == (directive == ".IFDEF"),
+                            Err(error) => {
+                                self.diagnostics.push(line.error(error));
+                                false
+                            }
+                        };
+                    conditionals.push(Conditional {
+                        start: line.clone(),
+                        span,
+                        active: condition,
+                        has_else: false,
+                    });
+                    output.push(line.listed_only());
+                }
+                Some((".ELSE", span)) => {
+                    match conditionals.last_mut() {
+                        Some(conditional) if !conditional.has_else => {
+                            conditional.active = !conditional.active;
+                            conditional.has_else = true;
+                        }
+                        Some(_) => self.diagnostics.push(
+                            line.error(LineError::new(span, "The block already has an .ELSE")),
+                        ),
+                        None => self.diagnostics.push(
+                            line.error(LineError::new(span, ".ELSE without .IFDEF or .IFNDEF")),
+                        ),
+                    }
+                    output.push(line.listed_only());
+                }
+                Some((".ENDIF", span)) => {
+                    if conditionals.pop().is_none() {
+                        self.diagnostics.push(
+                            line.error(LineError::new(span, ".ENDIF without .IFDEF or .IFNDEF")),
+                        );
+                    }
+                    output.push(line.listed_only());
+                }
+                _ if !active => output.push(line.listed_only()),
+                Some((".DEFINE", span)) => {
+                    match tokens.get(1..) {
+                        Some(
+                            [Token {
+                                kind: TokenKind::Word(name),
+                                ..
+                            }, value @ ..],
+                        ) => {
+                            let value = match (value.first(), value.last()) {
+                                (Some(first), Some(last)) => {
+                                    text(&line.text, first.span, last.span)
+                                }
+                                _ => String::new(),
+                            };
+                            self.defines.insert(name.clone(), value);
+                        }
+                        _ => self.diagnostics.push(line.error(LineError::new(
+                            span,
+                            ".DEFINE takes a name and, optionally, a value",
+                        ))),
+                    }
+                    output.push(line.listed_only());
+                }
+                Some((".MACRO", span)) => {
+                    definition = Some(self.start_macro(line.clone(), &tokens, span));
+                    output.push(line.listed_only());
+                }
+                Some((".ENDM", span)) => {
+                    self.diagnostics
+                        .push(line.error(LineError::new(span, ".ENDM without .MACRO")));
+                    output.push(line.listed_only());
+                }
+                Some((".INCLUDE", span)) => {
+                    let included = self.include(&line, &tokens, span, depth);
+                    output.push(line.listed_only());
+                    output.extend(included);
+                }
+                _ => output.extend(self.call(line, &tokens, depth)),
+            }
+        }
+
+        if let Some(definition) = definition {
+            let name = definition.name.unwrap_or_default();
+            self.diagnostics.push(definition.start.error(LineError::new(
+                definition.span,
+                format!("The macro '{}' has no .ENDM", name),
+            )));
+        }
+        for conditional in conditionals {
+            self.diagnostics.push(
+                conditional
+                    .start
+                    .error(LineError::new(conditional.span, "The block has no .ENDIF")),
+            );
+        }
+        output
+    }
+
+    /// Reads the name and the parameters of a macro. The errors are reported, and the body is read anyway so it doesn't reach the assembler
+    fn start_macro(&mut self, line: SourceLine, tokens: &[Token], span: Span) -> Definition {
+        let mut definition = Definition {
+            name: None,
+            start: line,
+            span,
+            body: Vec::new(),
+            params: Vec::new(),
+        };
+
+        let Some(Token {
+            kind: TokenKind::Word(name),
+            span: name_span,
+        }) = tokens.get(1)
+        else {
+            self.diagnostics.push(definition.start.error(LineError::new(
+                span,
+                ".MACRO takes a name and the names of the parameters",
+            )));
+            return definition;
+        };
+        if is_operation(name) {
+            self.diagnostics.push(definition.start.error(LineError::new(
+                *name_span,
+                format!(
+                    "'{}' is an operation, it can't be the name of a macro",
+                    name
+                ),
+            )));
+            return definition;
+        }
+        if self.macros.contains_key(&name.to_uppercase()) {
+            self.diagnostics.push(definition.start.error(LineError::new(
+                *name_span,
+                format!("The macro '{}' is already defined", name),
+            )));
+            return definition;
+        }
+
+        // The parameters can be separated by commas or just by spaces
+        for token in &tokens[2..] {
+            match &token.kind {
+                TokenKind::Comma => {}
+                TokenKind::Word(param)
+                    if is_identifier(param) && !definition.params.contains(param) =>
+                {
+                    definition.params.push(param.clone())
+                }
+                _ => {
+                    self.diagnostics.push(definition.start.error(LineError::new(
+                        token.span,
+                        "Expected the name of a parameter, not used before in the macro",
+                    )));
+                    return definition;
+                }
+            }
+        }
+        definition.name = Some(name.to_uppercase());
+        definition
+    }
+
+    fn end_macro(&mut self, definition: Definition) {
+        if let Some(name) = definition.name {
+            self.macros.insert(
+                name,
+                Macro {
+                    params: definition.params,
+                    body: definition.body,
+                },
+            );
+        }
+    }
+
+    /// Returns the lines of the file included, already preprocessed
+    fn include(
+        &mut self,
+        line: &SourceLine,
+        tokens: &[Token],
+        span: Span,
+        depth: usize,
+    ) -> Vec<SourceLine> {
+        let [_, Token {
+            kind: TokenKind::Str(path),
+            span: path_span,
+        }] = tokens
+        else {
+            self.diagnostics.push(line.error(LineError::new(
+                span,
+                ".INCLUDE takes the path of a file between quotes",
+            )));
+            return Vec::new();
+        };
+
+        let path = Path::new(&line.file)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(path);
+        let full_path = canonical(&path);
+        if self.files.contains(&full_path) {
+            self.diagnostics.push(line.error(LineError::new(
+                *path_span,
+                format!("The file '{}' includes itself", path.display()),
+            )));
+            return Vec::new();
+        }
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) => {
+                self.diagnostics.push(line.error(LineError::new(
+                    *path_span,
+                    format!("Can't read '{}': {}", path.display(), e),
+                )));
+                return Vec::new();
+            }
+        };
+
+        self.files.push(full_path);
+        let lines = self.expand(source_lines(&path.display().to_string(), &source), depth);
+        self.files.pop();
+        lines
+    }
+
+    /// Replaces the defined names in the line and, if it uses a macro, expands it. The line stays before the expansion with just its label, if it has one, so the label gets the address of the first word of the expansion
+    fn call(&mut self, line: SourceLine, tokens: &[Token], depth: usize) -> Vec<SourceLine> {
+        let line = SourceLine {
+            text: self.substitute(&line.text, tokens),
+            ..line
+        };
+        let tokens = tokenize(&line.text).unwrap_or_default();
+
+        let is_macro = |token: &Token| matches!(&token.kind, TokenKind::Word(word) if self.macros.contains_key(&word.to_uppercase()));
+        let index = match tokens.iter().position(is_macro) {
+            // An operation after the name of a macro means that it was meant as a label
+            Some(0) if matches!(tokens.get(1), Some(Token { kind: TokenKind::Word(word), .. }) if is_operation(word)) =>
+            {
+                self.diagnostics.push(line.error(LineError::new(
+                    tokens[0].span,
+                    format!(
+                        "'{}' is the name of a macro, it can't be a label",
+                        text(&line.text, tokens[0].span, tokens[0].span)
+                    ),
+                )));
+                return vec![line];
+            }
+            Some(0) => 0,
+            Some(1) if matches!(&tokens[0].kind, TokenKind::Word(word) if !is_operation(word)) => 1,
+            _ => return vec![line],
+        };
+
+        let label = match index {
+            1 => text(&line.text, tokens[0].span, tokens[0].span),
+            _ => String::new(),
+        };
+        let mut output = vec![SourceLine {
+            text: label,
+            written: Some(line.text.clone()),
+            ..line.clone()
+        }];
+
+        let span = tokens[index].span;
+        let name = text(&line.text, span, span).to_uppercase();
+        let args: Vec<String> = tokens[index + 1..]
+            .iter()
+            .filter(|token| token.kind != TokenKind::Comma)
+            .map(|token| text(&line.text, token.span, token.span))
+            .collect();
+        let params = self.macros[&name].params.len();
+        if args.len() != params {
+            self.diagnostics.push(line.error(LineError::new(
+                span,
+                format!(
+                    "Wrong number of arguments for the macro '{}': expected {}, found {}",
+                    name,
+                    params,
+                    args.len()
+                ),
+            )));
+            return output;
+        }
+        if depth >= MAX_EXPANSION_DEPTH {
+            self.diagnostics.push(line.error(LineError::new(
+                span,
+                format!(
+                    "The macro '{}' is expanded inside itself too many times",
+                    name
+                ),
+            )));
+            return output;
+        }
+
+        self.expansions += 1;
+        let note = format!(
+            "In the macro '{}' used in {}:{}",
+            name, line.file, line.line
+        );
+        let note = match &line.expansion {
+            Some(outer) => format!("{}\n{}", note, outer),
+            None => note,
+        };
+        let definition = &self.macros[&name];
+        let body = definition
+            .body
+            .iter()
+            .map(|body_line| SourceLine {
+                text: replace_params(&body_line.text, &definition.params, &args, self.expansions),
+                expansion: Some(note.clone()),
+                ..body_line.clone()
+            })
+            .collect();
+        output.extend(self.expand(body, depth + 1));
+        output
+    }
+
+    /// Replaces the words that are defined names with their values. The names defined without a value are left as they are
+    fn substitute(&self, line: &str, tokens: &[Token]) -> String {
+        let chars: Vec<char> = line.chars().collect();
+        let mut result = String::new();
+        let mut last = 0;
+        for token in tokens {
+            let TokenKind::Word(word) = &token.kind else {
+                continue;
+            };
+            match self.defines.get(word) {
+                Some(value) if !value.is_empty() => {
+                    let start = token.span.column - 1;
+                    result.extend(&chars[last..start]);
+                    result.push_str(value);
+                    last = start + token.span.len;
+                }
+                _ => {}
+            }
+        }
+        result.extend(&chars[last..]);
+        result
+    }
+}
+
+/// Returns the name that is the only operand of a directive
+fn name_operand<'a>(
+    directive: &str,
+    tokens: &'a [Token],
+    span: Span,
+) -> Result<&'a str, LineError> {
+    match tokens {
+        [_, Token {
+            kind: TokenKind::Word(name),
+            ..
+        }] => Ok(name),
+        _ => Err(LineError::new(span, format!("{} takes a name", directive))),
+    }
+}
+
+/// Replaces every \param in a line of the body of a macro with its argument, and \@ with the number of the expansion, so the labels in the macro are different each time it is used. The strings and the comment are left as they are
+fn replace_params(line: &str, params: &[String], args: &[String], expansion: usize) -> String {
+    let mut result = String::new();
+    let mut chars = line.chars().peekable();
+    let mut in_string = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            ';' if !in_string => {
+                result.push(c);
+                result.extend(chars.by_ref());
+            }
+            '"' => {
+                in_string = !in_string;
+                result.push(c);
+            }
+            '\\' if in_string => {
+                // The escaped character can be a quote, that doesn't end the string
+                result.push(c);
+                result.extend(chars.next());
+            }
+            '\\' if chars.peek() == Some(&'@') => {
+                chars.next();
+                result.push_str(&expansion.to_string());
+            }
+            '\\' => {
+                let mut name = String::new();
+                while let Some(&n) = chars.peek() {
+                    if n.is_alphanumeric() || n == '_' {
+                        name.push(n);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                match params.iter().position(|param| *param == name) {
+                    Some(index) => result.push_str(&args[index]),
+                    None => {
+                        result.push(c);
+                        result.push_str(&name);
+                    }
+                }
+            }
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Returns the text of the line from the start of a span to the end of another
+fn text(line: &str, from: Span, to: Span) -> String {
+    line.chars()
+        .skip(from.column - 1)
+        .take(to.column + to.len - from.column)
+        .collect()
+}
+
+fn is_identifier(word: &str) -> bool {
+    let mut chars = word.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Returns the absolute path of a file, or the path as it is if the file can't be found
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Preprocessor, SourceLine};
+    use crate::errors::VmError;
+
+    use std::{env, fs, process, thread};
+
+    /// Returns the text of the lines of a source after the preprocessing, leaving out the empty ones
+    fn texts(source: &str) -> Vec<String> {
+        Preprocessor::new()
+            .process("<source>", source)
+            .unwrap()
+            .into_iter()
+            .map(|line| line.text)
+            .filter(|text| !text.is_empty())
+            .collect()
+    }
+
+    /// Returns the errors of a source that can't be preprocessed, as (line, column, message)
+    fn errors(source: &str) -> Vec<(usize, usize, String)> {
+        match Preprocessor::new().process("<source>", source) {
+            Err(VmError::AssemblyErrors(diagnostics)) => diagnostics
+                .into_iter()
+                .map(|diagnostic| (diagnostic.line, diagnostic.span.column, diagnostic.message))
+                .collect(),
+            _ => panic!("The source was expected to have errors"),
+        }
+    }
+
+    #[test]
+    fn test_macro_is_expanded_with_its_arguments() {
+        let source = ".MACRO PUSH reg\nADD R6, R6, #-1\nSTR \\reg, R6, #0\n.ENDM\nPUSH R1\npush R2";
+
+        assert_eq!(
+            vec![
+                "ADD R6, R6, #-1",
+                "STR R1, R6, #0",
+                "ADD R6, R6, #-1",
+                "STR R2, R6, #0"
+            ],
+            texts(source)
+        );
+    }
+
+    #[test]
+    fn test_macro_labels_are_different_in_every_expansion() {
+        let source = ".MACRO WAIT\nLOOP\\@ BRnzp LOOP\\@\n.ENDM\nWAIT\nWAIT";
+
+        assert_eq!(
+            vec!["LOOP1 BRnzp LOOP1", "LOOP2 BRnzp LOOP2"],
+            texts(source)
+        );
+    }
+
+    #[test]
+    fn test_macro_with_label_leaves_the_label_in_its_own_line() {
+        let source = ".MACRO TWICE a\nADD \\a, \\a, \\a\n.ENDM\nSTART TWICE R3";
+
+        assert_eq!(vec!["START", "ADD R3, R3, R3"], texts(source));
+    }
+
+    #[test]
+    fn test_macro_can_use_other_macros() {
+        let source =
+            ".MACRO INC r\nADD \\r, \\r, #1\n.ENDM\n.MACRO INC2 r\nINC \\r\nINC \\r\n.ENDM\nINC2 R0";
+
+        assert_eq!(vec!["ADD R0, R0, #1", "ADD R0, R0, #1"], texts(source));
+    }
+
+    #[test]
+    fn test_lines_of_the_macro_keep_the_place_they_were_written() {
+        let lines = Preprocessor::new()
+            .process("a.asm", ".MACRO NOTHING\nAND R0, R0, #0\n.ENDM\nNOTHING")
+            .unwrap();
+
+        // The lines of the definition are only there for the listing
+        assert_eq!(Some("AND R0, R0, #0"), lines[1].written.as_deref());
+        assert_eq!(
+            vec!["", "", ""],
+            lines[..3]
+                .iter()
+                .map(|line| line.text.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![
+                SourceLine {
+                    file: "a.asm".to_string(),
+                    line: 4,
+                    text: String::new(),
+                    expansion: None,
+                    written: Some("NOTHING".to_string()),
+                },
+                SourceLine {
+                    file: "a.asm".to_string(),
+                    line: 2,
+                    text: "AND R0, R0, #0".to_string(),
+                    expansion: Some("In the macro 'NOTHING' used in a.asm:4".to_string()),
+                    written: None,
+                }
+            ],
+            lines[3..]
+        );
+    }
+
+    #[test]
+    fn test_label_with_the_name_of_a_macro_is_an_error() {
+        let source = ".MACRO PUSH reg\nSTR \\reg, R6, #0\n.ENDM\nPUSH .FILL x0";
+
+        assert_eq!(
+            vec![(
+                4,
+                1,
+                "'PUSH' is the name of a macro, it can't be a label".to_string()
+            )],
+            errors(source)
+        );
+    }
+
+    #[test]
+    fn test_macro_with_wrong_number_of_arguments_is_an_error() {
+        let source = ".MACRO PUSH reg\nSTR \\reg, R6, #0\n.ENDM\nPUSH R1, R2";
+
+        assert_eq!(
+            vec![(
+                4,
+                1,
+                "Wrong number of arguments for the macro 'PUSH': expected 1, found 2".to_string()
+            )],
+            errors(source)
+        );
+    }
+
+    #[test]
+    fn test_macro_that_uses_itself_is_an_error() {
+        let errors = errors(".MACRO FOREVER\nFOREVER\n.ENDM\nFOREVER");
+
+        assert_eq!(1, errors.len());
+        assert_eq!(2, errors[0].0);
+    }
+
+    #[test]
+    fn test_macro_without_endm_is_an_error() {
+        assert_eq!(
+            vec![(1, 1, "The macro 'PUSH' has no .ENDM".to_string())],
+            errors(".MACRO PUSH reg\nSTR \\reg, R6, #0")
+        );
+    }
+
+    #[test]
+    fn test_defined_names_are_replaced_by_their_values() {
+        let source = ".DEFINE SP R6\n.DEFINE SIZE #4\nADD SP, SP, SIZE ; SP";
+
+        assert_eq!(vec!["ADD R6, R6, #4 ; SP"], texts(source));
+    }
+
+    #[test]
+    fn test_conditional_blocks_keep_the_lines_of_the_true_branch() {
+        let source =
+            ".DEFINE DEBUG\n.IFDEF DEBUG\nOUT\n.ELSE\nHALT\n.ENDIF\n.IFNDEF DEBUG\nGETC\n.ENDIF";
+
+        assert_eq!(vec!["OUT"], texts(source));
+    }
+
+    #[test]
+    fn test_nested_conditional_blocks_inside_a_skipped_one_are_skipped() {
+        let source = ".DEFINE A\n.IFDEF B\n.IFDEF A\nOUT\n.ELSE\nGETC\n.ENDIF\n.ENDIF";
+
+        assert!(texts(source).is_empty());
+    }
+
+    #[test]
+    fn test_names_defined_from_outside_are_seen_by_the_source() {
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.define("DEBUG", "");
+        let lines = preprocessor
+            .process("<source>", ".IFDEF DEBUG\nOUT\n.ENDIF")
+            .unwrap();
+
+        assert_eq!("OUT", lines[1].text);
+    }
+
+    #[test]
+    fn test_unbalanced_conditional_blocks_are_errors() {
+        assert_eq!(
+            vec![
+                (1, 1, ".ENDIF without .IFDEF or .IFNDEF".to_string()),
+                (2, 1, "The block has no .ENDIF".to_string())
+            ],
+            errors(".ENDIF\n.IFDEF A")
+        );
+    }
+
+    #[test]
+    fn test_include_reads_the_file_from_the_directory_of_the_source() {
+        // A directory for this test alone, so other runs can't write in it at the same time
+        let dir = env::temp_dir().join(format!(
+            "lc3_vm_test_include_{}_{:?}",
+            process::id(),
+            thread::current().id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("stack.asm"),
+            ".MACRO POP reg\nLDR \\reg, R6, #0\nADD R6, R6, #1\n.ENDM\n",
+        )
+        .unwrap();
+        let main = dir.join("main.asm").display().to_string();
+
+        let lines = Preprocessor::new()
+            .process(&main, ".INCLUDE \"stack.asm\"\nPOP R2")
+            .unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        // The .INCLUDE and the use of the macro stay before the lines they are replaced with
+        assert_eq!(Some(".INCLUDE \"stack.asm\""), lines[0].written.as_deref());
+        assert_eq!(Some("POP R2"), lines[5].written.as_deref());
+        assert_eq!("LDR R2, R6, #0", lines[6].text);
+        assert!(lines[6].file.ends_with("stack.asm"));
+        assert_eq!(2, lines[6].line);
+    }
+
+    #[test]
+    fn test_directives_and_skipped_lines_are_kept_for_the_listing() {
+        let source = ".DEFINE A\n.IFDEF B\nOUT\n.ELSE\nHALT\n.ENDIF";
+        let lines = Preprocessor::new().process("<source>", source).unwrap();
+
+        assert_eq!(
+            vec![1, 2, 3, 4, 5, 6],
+            lines.iter().map(|line| line.line).collect::<Vec<_>>()
+        );
+        assert_eq!(Some("OUT"), lines[2].written.as_deref());
+        assert_eq!("", lines[2].text);
+        assert_eq!("HALT", lines[4].text);
+    }
+
+    #[test]
+    fn test_include_of_a_missing_file_is_an_error() {
+        let errors = errors(".INCLUDE \"lc3_vm_missing.asm\"");
+
+        assert_eq!((1, 10), (errors[0].0, errors[0].1));
+    }
+}
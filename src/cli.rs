@@ -0,0 +1,189 @@
+use std::path::PathBuf;
+
+use lc3_vm::{errors::VmError, SymbolTable};
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(
+    name = "lc3-vm",
+    about = "An implementation of the LC-3 virtual machine"
+)]
+pub enum Command {
+    /// Loads one or more object images in the same machine and executes them
+    Run(RunOptions),
+    /// Assembles an LC-3 source file into an object image
+    Asm(AsmOptions),
+}
+
+#[derive(StructOpt)]
+pub struct RunOptions {
+    /// Object images to load, each one at its own origin
+    #[structopt(parse(from_os_str), required = true)]
+    pub images: Vec<PathBuf>,
+
+    /// Position in the list, starting from 1, of the image whose origin is the first instruction to execute
+    #[structopt(long, default_value = "1")]
+    pub start_image: usize,
+
+    /// Address (x3000, 0x3000 or #12288) or label of the first instruction to execute. Overrides the origin of the start image
+    #[structopt(long)]
+    pub entry: Option<String>,
+
+    /// Stops before executing the instruction in this address or label, and shows the registers. Can be given more than once
+    #[structopt(long = "break", number_of_values = 1)]
+    pub breakpoints: Vec<String>,
+
+    /// Symbol tables (.sym) to load, besides the ones next to the images
+    #[structopt(long, number_of_values = 1, parse(from_os_str))]
+    pub symbols: Vec<PathBuf>,
+
+    /// Stops after executing this many instructions
+    #[structopt(long)]
+    pub limit: Option<u64>,
+
+    /// Writes every instruction executed, and the registers after it, to the stderr
+    #[structopt(long)]
+    pub trace: bool,
+
+    /// Dispatches TRAP through the trap vector table in memory (x0000-x00FF), instead of doing the standard routines in the vm. The pc is saved in R7 and the routines return with RET. The routines run in user mode, so it can't go with --access-control: use --supervisor-traps for that
+    #[structopt(long, conflicts_with = "access-control")]
+    pub vectored_traps: bool,
+
+    /// Like --vectored-traps, but the routines run in supervisor mode, with the psr and the pc saved in the supervisor stack, and return with RTI
+    #[structopt(long)]
+    pub supervisor_traps: bool,
+
+    /// On exceptions, jumps to the handlers in the interrupt vector table instead of stopping with an error. Use it when an operating system is loaded
+    #[structopt(long)]
+    pub vectored_exceptions: bool,
+
+    /// Boots the bundled operating system, which serves the traps and the exceptions and then jumps to the user program in user mode
+    #[structopt(long)]
+    pub os: bool,
+
+    /// Makes accesses to the system space (x0000-x2FFF) and to the device registers (xFE00-xFFFF) in user mode access control violations
+    #[structopt(long)]
+    pub access_control: bool,
+
+    /// Leaves the terminal settings as they are, instead of disabling the echo and the line buffering
+    #[structopt(long)]
+    pub no_raw_terminal: bool,
+
+    /// File with the keys to type before reading from the keyboard
+    #[structopt(long, parse(from_os_str))]
+    pub input: Option<PathBuf>,
+}
+
+#[derive(StructOpt)]
+pub struct AsmOptions {
+    /// Source file to assemble
+    #[structopt(parse(from_os_str))]
+    pub source: PathBuf,
+
+    /// Where to write the object image. By default, the source path with the .obj extension
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+
+    /// Also writes a listing (.lst) next to the object image, with every line of the source, its address and its encoding in hexadecimal and in binary
+    #[structopt(short, long)]
+    pub listing: bool,
+
+    /// Defines a name for the preprocessor, as NAME or NAME=value, like a .DEFINE at the start of the source. Can be given more than once
+    #[structopt(short = "D", long = "define", number_of_values = 1, parse(from_str = parse_define))]
+    pub defines: Vec<(String, String)>,
+}
+
+/// Splits a definition written as NAME=value. A definition without a value has an empty one
+pub fn parse_define(s: &str) -> (String, String) {
+    match s.split_once('=') {
+        Some((name, value)) => (name.to_string(), value.to_string()),
+        None => (s.to_string(), String::new()),
+    }
+}
+
+/// Parses an address written in hexadecimal (x3000 or 0x3000) or in decimal (#12288 or 12288)
+pub fn parse_address(s: &str) -> Result<u16, String> {
+    let result = if let Some(hex) = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix('x'))
+        .or_else(|| s.strip_prefix('X'))
+    {
+        u16::from_str_radix(hex, 16)
+    } else {
+        s.strip_prefix('#').unwrap_or(s).parse::<u16>()
+    };
+
+    result.map_err(|_| format!("'{}' is not a valid address", s))
+}
+
+/// Takes an address like parse_address does, or the address of a label in the symbol table
+pub fn resolve_address(s: &str, symbols: &SymbolTable) -> Result<u16, VmError> {
+    parse_address(s)
+        .ok()
+        .or_else(|| symbols.address(s))
+        .ok_or_else(|| VmError::UnknownLabel(s.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_address, parse_define, resolve_address, Command};
+    use lc3_vm::SymbolTable;
+    use structopt::StructOpt;
+
+    #[test]
+    fn test_parse_address_in_hexadecimal() {
+        assert_eq!(Ok(0x3000), parse_address("x3000"));
+        assert_eq!(Ok(0x3000), parse_address("0x3000"));
+        assert_eq!(Ok(0xFE00), parse_address("xfe00"));
+    }
+
+    #[test]
+    fn test_parse_address_in_decimal() {
+        assert_eq!(Ok(12288), parse_address("#12288"));
+        assert_eq!(Ok(12288), parse_address("12288"));
+    }
+
+    #[test]
+    fn test_parse_address_out_of_range_is_an_error() {
+        assert!(parse_address("x10000").is_err());
+        assert!(parse_address("LOOP").is_err());
+    }
+
+    #[test]
+    fn test_resolve_address_with_a_label() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert("MAIN", 0x3100);
+
+        assert_eq!(0x3100, resolve_address("MAIN", &symbols).unwrap());
+        assert_eq!(0x3000, resolve_address("x3000", &symbols).unwrap());
+        assert!(resolve_address("LOOP", &symbols).is_err());
+    }
+
+    #[test]
+    fn test_vectored_traps_with_access_control_is_an_error() {
+        // The table and the routines are in the system space, which user mode can't access
+        let args = [
+            "lc3-vm",
+            "run",
+            "--vectored-traps",
+            "--access-control",
+            "a.obj",
+        ];
+        assert!(Command::from_iter_safe(args).is_err());
+
+        let args = [
+            "lc3-vm",
+            "run",
+            "--supervisor-traps",
+            "--access-control",
+            "a.obj",
+        ];
+        assert!(Command::from_iter_safe(args).is_ok());
+    }
+
+    #[test]
+    fn test_parse_define_with_and_without_value() {
+        assert_eq!(("SP".to_string(), "R6".to_string()), parse_define("SP=R6"));
+        assert_eq!(("DEBUG".to_string(), String::new()), parse_define("DEBUG"));
+    }
+}
@@ -1,16 +1,69 @@
+use crate::{asm::diagnostic::Diagnostic, hardware::consts, symbols::SymbolTable};
+
 use std::{fmt, io::Error};
 
 #[derive(Debug)]
 pub enum VmError {
     OutOfBoundsError,
     KeyboardInputError(Error),
+    ConsoleOutputError(Error),
     NotEnoughArguments,
+    ImageIndexOutOfRange(usize),
     IncorrectFileNameError(String, Error),
     BadFileError(Error),
+    OutputFileError(String, Error),
+    ImageTooLarge {
+        image: String,
+        origin: u16,
+        len: usize,
+    },
+    ImageOverlap {
+        image: String,
+        other: String,
+        address: u16,
+    },
+    IllegalOpcode {
+        pc: u16,
+        instr: u16,
+    },
+    UnknownTrapVector {
+        pc: u16,
+        instr: u16,
+    },
+    PrivilegeViolation {
+        pc: u16,
+        instr: u16,
+    },
+    AccessViolation {
+        pc: u16,
+        address: u16,
+    },
+    DeviceAddressInUse(u16),
+    UnknownLabel(String),
+    TerminalError(Error),
+    AssemblyErrors(Vec<Diagnostic>),
 }
 
-impl fmt::Display for VmError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl VmError {
+    /// Returns the entry of the interrupt vector table for the errors that are architectural exceptions
+    pub fn exception_vector(&self) -> Option<u16> {
+        match self {
+            Self::PrivilegeViolation { .. } => Some(consts::EX_PRIVILEGE),
+            Self::IllegalOpcode { .. } => Some(consts::EX_ILLEGAL_OPCODE),
+            Self::AccessViolation { .. } => Some(consts::EX_ACCESS_VIOLATION),
+            _ => None,
+        }
+    }
+
+    /// Returns the message of the error, with the addresses of the program shown with their labels
+    pub fn describe(&self, symbols: &SymbolTable) -> String {
+        let mut message = String::new();
+        // Writing to a String can't fail
+        let _ = self.write_message(&mut message, symbols);
+        message
+    }
+
+    fn write_message(&self, f: &mut dyn fmt::Write, symbols: &SymbolTable) -> fmt::Result {
         match self {
             Self::OutOfBoundsError => {
                 write!(f, "The register required is out of bounds")
@@ -18,18 +71,111 @@ impl fmt::Display for VmError {
             Self::KeyboardInputError(e) => {
                 write!(f, "An error ocurred while reading Keyboard Input: {}", e)
             }
+            Self::ConsoleOutputError(e) => {
+                write!(f, "An error ocurred while writing to the console: {}", e)
+            }
             Self::NotEnoughArguments => {
                 write!(
                     f,
-                    "There are arguments missing. Usage: cargo run [image-file1] ..."
+                    "There are arguments missing. Usage: lc3-vm run [image-file1] ..."
                 )
             }
+            Self::ImageIndexOutOfRange(index) => {
+                write!(f, "There is no image number {} to start from", index)
+            }
             Self::IncorrectFileNameError(name, e) => {
                 write!(f, "Error opening the file '{}': {}", name, e)
             }
+            Self::OutputFileError(name, e) => {
+                write!(f, "Error writing the file '{}': {}", name, e)
+            }
             Self::BadFileError(e) => {
                 write!(f, "The file had an error while reading: {}", e)
             }
+            Self::ImageTooLarge { image, origin, len } => {
+                write!(
+                    f,
+                    "The image {} has {} words from x{:04X}, which go past the end of the memory",
+                    image, len, origin
+                )
+            }
+            Self::ImageOverlap {
+                image,
+                other,
+                address,
+            } => {
+                write!(
+                    f,
+                    "The image {} overlaps the image {} at x{:04X}",
+                    image, other, address
+                )
+            }
+            Self::IllegalOpcode { pc, instr } => {
+                write!(
+                    f,
+                    "Illegal opcode {} in the instruction x{:04X} at {}",
+                    instr >> 12,
+                    instr,
+                    symbols.describe(*pc)
+                )
+            }
+            Self::UnknownLabel(label) => {
+                write!(
+                    f,
+                    "There is no label '{}' in the symbol tables loaded",
+                    label
+                )
+            }
+            Self::DeviceAddressInUse(address) => {
+                write!(
+                    f,
+                    "The address x{:04X} is already used by another device",
+                    address
+                )
+            }
+            Self::PrivilegeViolation { pc, instr } => {
+                write!(
+                    f,
+                    "The instruction x{:04X} at {} can only be executed in supervisor mode",
+                    instr,
+                    symbols.describe(*pc)
+                )
+            }
+            Self::AccessViolation { pc, address } => {
+                write!(
+                    f,
+                    "The instruction at {} accessed x{:04X}, which is reserved to supervisor mode",
+                    symbols.describe(*pc),
+                    address
+                )
+            }
+            Self::AssemblyErrors(diagnostics) => {
+                for diagnostic in diagnostics {
+                    writeln!(f, "{}", diagnostic)?;
+                }
+                match diagnostics.len() {
+                    1 => write!(f, "The source has 1 error"),
+                    n => write!(f, "The source has {} errors", n),
+                }
+            }
+            Self::TerminalError(e) => {
+                write!(f, "Error setting up the terminal: {}", e)
+            }
+            Self::UnknownTrapVector { pc, instr } => {
+                write!(
+                    f,
+                    "Unknown trap vector x{:02X} in the instruction x{:04X} at {}",
+                    instr & 0xFF,
+                    instr,
+                    symbols.describe(*pc)
+                )
+            }
         }
     }
 }
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_message(f, &SymbolTable::new())
+    }
+}
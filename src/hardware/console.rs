@@ -0,0 +1,230 @@
+use crate::errors::VmError;
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fs::File,
+    io::{self, ErrorKind, Read, Write},
+    mem::ManuallyDrop,
+    os::unix::io::FromRawFd,
+    rc::Rc,
+};
+
+/// The keyboard and the display of the machine. Trap routines and keyboard registers go through it
+pub trait Console {
+    /// Returns the next key typed, or None if there is no key available
+    fn read_char(&mut self) -> Result<Option<u8>, VmError>;
+
+    /// Checks, without blocking, if there is a key to read
+    fn key_available(&mut self) -> Result<bool, VmError>;
+
+    /// Writes a character in the display
+    fn write_char(&mut self, c: u8) -> Result<(), VmError>;
+
+    /// Makes sure that all the characters written are shown
+    fn flush(&mut self) -> Result<(), VmError> {
+        Ok(())
+    }
+
+    /// Blocks until there is a key to read. Returns false if the console can't wait, because no more keys will come
+    fn wait_for_key(&mut self) -> Result<bool, VmError> {
+        Ok(false)
+    }
+}
+
+/// Reads the keys from the stdin and writes to the stdout
+#[derive(Default)]
+pub struct StdConsole;
+
+impl Console for StdConsole {
+    fn read_char(&mut self) -> Result<Option<u8>, VmError> {
+        // The std stdin is buffered, so keys could be waiting there while a poll on the descriptor says there are none.
+        // The descriptor is blocking, so this waits until a key is typed
+        // SAFETY: the stdin descriptor is open for the whole process, and ManuallyDrop keeps the File from closing it
+        let mut stdin = ManuallyDrop::new(unsafe { File::from_raw_fd(libc::STDIN_FILENO) });
+
+        let mut buf = [0; 1];
+        match stdin.read_exact(&mut buf) {
+            Ok(_) => Ok(Some(buf[0])),
+            Err(e) => Err(VmError::KeyboardInputError(e)),
+        }
+    }
+
+    fn key_available(&mut self) -> Result<bool, VmError> {
+        let mut fds = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        // A timeout of 0 makes poll return immediately
+        // SAFETY: fds is a valid pollfd that lives during the call, and the count of 1 matches it
+        match unsafe { libc::poll(&mut fds, 1, 0) } {
+            -1 => Err(VmError::KeyboardInputError(io::Error::last_os_error())),
+            0 => Ok(false),
+            _ => Ok(fds.revents & libc::POLLIN != 0),
+        }
+    }
+
+    fn write_char(&mut self, c: u8) -> Result<(), VmError> {
+        io::stdout()
+            .write_all(&[c])
+            .map_err(VmError::ConsoleOutputError)
+    }
+
+    fn flush(&mut self) -> Result<(), VmError> {
+        io::stdout().flush().map_err(VmError::ConsoleOutputError)
+    }
+
+    fn wait_for_key(&mut self) -> Result<bool, VmError> {
+        let mut fds = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        // A negative timeout makes poll wait until the stdin is ready. At the end of the input it is ready too, and the read fails
+        // SAFETY: fds is a valid pollfd that lives during the call, and the count of 1 matches it
+        match unsafe { libc::poll(&mut fds, 1, -1) } {
+            -1 if io::Error::last_os_error().kind() != ErrorKind::Interrupted => {
+                Err(VmError::KeyboardInputError(io::Error::last_os_error()))
+            }
+            _ => Ok(true),
+        }
+    }
+}
+
+/// Takes the keys from a buffer and keeps the output in memory, so it can be inspected
+pub struct BufferConsole {
+    input: VecDeque<u8>,
+    output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl BufferConsole {
+    pub fn new(input: &[u8]) -> Self {
+        BufferConsole {
+            input: input.iter().copied().collect(),
+            output: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    /// Returns a handle to the output, that stays valid after moving the console into a vm
+    pub fn output(&self) -> Rc<RefCell<Vec<u8>>> {
+        Rc::clone(&self.output)
+    }
+
+    /// Adds keys at the end of the input
+    pub fn push_input(&mut self, input: &[u8]) {
+        self.input.extend(input.iter().copied());
+    }
+}
+
+impl Console for BufferConsole {
+    fn read_char(&mut self) -> Result<Option<u8>, VmError> {
+        Ok(self.input.pop_front())
+    }
+
+    fn key_available(&mut self) -> Result<bool, VmError> {
+        Ok(!self.input.is_empty())
+    }
+
+    fn write_char(&mut self, c: u8) -> Result<(), VmError> {
+        self.output.borrow_mut().push(c);
+        Ok(())
+    }
+}
+
+/// Types the keys of a script, and then continues reading from the stdin. The output goes to the stdout
+pub struct ScriptedConsole {
+    script: VecDeque<u8>,
+    console: StdConsole,
+}
+
+impl ScriptedConsole {
+    pub fn new(script: &[u8]) -> Self {
+        ScriptedConsole {
+            script: script.iter().copied().collect(),
+            console: StdConsole,
+        }
+    }
+}
+
+impl Console for ScriptedConsole {
+    fn read_char(&mut self) -> Result<Option<u8>, VmError> {
+        match self.script.pop_front() {
+            Some(c) => Ok(Some(c)),
+            None => self.console.read_char(),
+        }
+    }
+
+    fn key_available(&mut self) -> Result<bool, VmError> {
+        Ok(!self.script.is_empty() || self.console.key_available()?)
+    }
+
+    fn write_char(&mut self, c: u8) -> Result<(), VmError> {
+        self.console.write_char(c)
+    }
+
+    fn flush(&mut self) -> Result<(), VmError> {
+        self.console.flush()
+    }
+
+    fn wait_for_key(&mut self) -> Result<bool, VmError> {
+        if self.script.is_empty() {
+            self.console.wait_for_key()
+        } else {
+            Ok(true)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BufferConsole, Console, ScriptedConsole};
+
+    #[test]
+    fn test_buffer_console_reads_the_input_in_order() {
+        let mut console = BufferConsole::new(b"ab");
+
+        assert_eq!(Some(b'a'), console.read_char().unwrap());
+        assert_eq!(Some(b'b'), console.read_char().unwrap());
+        assert_eq!(None, console.read_char().unwrap());
+    }
+
+    #[test]
+    fn test_buffer_console_has_keys_available_until_the_input_is_consumed() {
+        let mut console = BufferConsole::new(b"a");
+
+        assert!(console.key_available().unwrap());
+        console.read_char().unwrap();
+        assert!(!console.key_available().unwrap());
+    }
+
+    #[test]
+    fn test_buffer_console_keeps_the_output() {
+        // The output handle sees what is written after it was taken
+        let mut console = BufferConsole::new(b"");
+        let output = console.output();
+
+        console.write_char(b'h').unwrap();
+        console.write_char(b'i').unwrap();
+
+        assert_eq!(b"hi".to_vec(), *output.borrow());
+    }
+
+    #[test]
+    fn test_buffer_console_does_not_wait_for_keys() {
+        // No keys are added while the vm runs, so waiting would never end
+        let mut console = BufferConsole::new(b"");
+
+        assert!(!console.wait_for_key().unwrap());
+    }
+
+    #[test]
+    fn test_scripted_console_types_the_script_first() {
+        let mut console = ScriptedConsole::new(b"w");
+
+        assert!(console.key_available().unwrap());
+        assert_eq!(Some(b'w'), console.read_char().unwrap());
+    }
+}
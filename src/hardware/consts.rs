@@ -3,11 +3,24 @@ pub const FL_POS: u16 = 1 << 0;
 pub const FL_ZRO: u16 = 1 << 1; /* Z */
 pub const FL_NEG: u16 = 1 << 2; /* N */
 
+// Processor Status Register
+pub const PSR_USER: u16 = 1 << 15; /* privilege, 1 is user mode */
+pub const PSR_PRIORITY: u16 = 0x7 << 8; /* priority level */
+pub const PSR_COND: u16 = FL_NEG | FL_ZRO | FL_POS; /* condition codes */
+
 // Memory
-pub const MEMORY_MAX: usize = u16::MAX as usize;
+pub const MEMORY_MAX: usize = 1 << 16; /* x0000-xFFFF */
+
+pub const USER_SPACE_START: u16 = 0x3000; /* x0000-x2FFF is system space */
+pub const DEVICE_SPACE_START: u16 = 0xFE00; /* xFE00-xFFFF are the device registers */
 
 pub const MR_KBSR: u16 = 0xFE00; /* keyboard status */
 pub const MR_KBDR: u16 = 0xFE02; /* keyboard data */
+pub const MR_DSR: u16 = 0xFE04; /* display status */
+pub const MR_DDR: u16 = 0xFE06; /* display data */
+pub const MR_TSR: u16 = 0xFE08; /* timer status */
+pub const MR_TIR: u16 = 0xFE0A; /* timer interval */
+pub const MR_MCR: u16 = 0xFFFE; /* machine control */
 
 // Registers
 pub const RR0: u16 = 0;
@@ -23,3 +36,15 @@ pub const RCOND: u16 = 9;
 pub const RCOUNT: u16 = 10;
 
 pub const PC_START: u16 = 0x3000;
+
+// Vector tables
+pub const TRAP_VECTOR_TABLE: u16 = 0x0000; /* x0000-x00FF, trap service routines */
+pub const INTERRUPT_VECTOR_TABLE: u16 = 0x0100; /* x0100-x01FF, interrupts and exceptions */
+
+// Exception vectors
+pub const EX_PRIVILEGE: u16 = 0x00; /* privilege mode violation */
+pub const EX_ILLEGAL_OPCODE: u16 = 0x01; /* illegal opcode */
+pub const EX_ACCESS_VIOLATION: u16 = 0x02; /* access control violation */
+
+// Stacks
+pub const SSP_START: u16 = 0x3000; /* the supervisor stack grows down from the start of user space */
@@ -0,0 +1,87 @@
+use crate::{
+    errors::VmError,
+    hardware::{console::Console, consts},
+};
+
+use super::Device;
+
+/// Set in the status register when the display can take a new character. The console takes them right away, so it is always set
+const DSR_READY: u16 = 1 << 15;
+
+/// The display status (DSR) and data (DDR) registers
+#[derive(Default)]
+pub struct Display {
+    status: u16,
+}
+
+impl Display {
+    pub fn new() -> Self {
+        Display { status: DSR_READY }
+    }
+}
+
+impl Device for Display {
+    fn read(&mut self, address: u16, _console: &mut dyn Console) -> Result<u16, VmError> {
+        match address {
+            consts::MR_DSR => Ok(self.status | DSR_READY),
+            _ => Ok(0),
+        }
+    }
+
+    fn write(
+        &mut self,
+        address: u16,
+        value: u16,
+        console: &mut dyn Console,
+    ) -> Result<(), VmError> {
+        match address {
+            consts::MR_DSR => self.status = value & !DSR_READY,
+            consts::MR_DDR => {
+                // Only the low byte is a character
+                console.write_char(value as u8)?;
+                console.flush()?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Display;
+    use crate::hardware::{
+        console::{BufferConsole, Console},
+        consts,
+        devices::Device,
+    };
+
+    #[test]
+    fn test_display_status_is_always_ready() {
+        let mut display = Display::new();
+        let mut console = BufferConsole::new(b"");
+
+        assert_eq!(1 << 15, display.read(consts::MR_DSR, &mut console).unwrap());
+
+        display.write(consts::MR_DSR, 0, &mut console).unwrap();
+        assert_eq!(1 << 15, display.read(consts::MR_DSR, &mut console).unwrap());
+    }
+
+    #[test]
+    fn test_display_writes_to_the_data_register_go_to_the_console() {
+        // Only the low byte of the value is written
+        let mut display = Display::new();
+        let mut console = BufferConsole::new(b"");
+        let output = console.output();
+
+        display
+            .write(consts::MR_DDR, b'h' as u16, &mut console)
+            .unwrap();
+        display
+            .write(consts::MR_DDR, 0x0100 | b'i' as u16, &mut console)
+            .unwrap();
+        console.flush().unwrap();
+
+        assert_eq!(b"hi".to_vec(), *output.borrow());
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{
+    errors::VmError,
+    hardware::{console::Console, consts},
+};
+
+use super::{Device, Interrupt};
+
+/// Set in the status register when there is a key in the data register
+const KBSR_READY: u16 = 1 << 15;
+
+/// Set in the status register by the program to get an interrupt when a key is typed
+pub const KBSR_INTERRUPT_ENABLE: u16 = 1 << 14;
+
+pub const KEYBOARD_INTERRUPT_VECTOR: u16 = 0x80;
+pub const KEYBOARD_PRIORITY: u16 = 4;
+
+/// The keyboard status (KBSR) and data (KBDR) registers
+#[derive(Default)]
+pub struct Keyboard {
+    status: u16,
+    data: u16,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Keyboard { status: 0, data: 0 }
+    }
+
+    /// Checks, without waiting, if a key was typed. The key stays in the data register until it is read
+    fn poll(&mut self, console: &mut dyn Console) -> Result<(), VmError> {
+        if self.status & KBSR_READY == 0 && console.key_available()? {
+            if let Some(c) = console.read_char()? {
+                self.data = c as u16;
+                self.status |= KBSR_READY;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Device for Keyboard {
+    fn read(&mut self, address: u16, console: &mut dyn Console) -> Result<u16, VmError> {
+        match address {
+            consts::MR_KBSR => {
+                self.poll(console)?;
+                Ok(self.status)
+            }
+            consts::MR_KBDR => {
+                self.status &= !KBSR_READY;
+                Ok(self.data)
+            }
+            _ => Ok(0),
+        }
+    }
+
+    fn write(
+        &mut self,
+        address: u16,
+        value: u16,
+        _console: &mut dyn Console,
+    ) -> Result<(), VmError> {
+        // The ready bit and the data can only be changed by the keyboard
+        if address == consts::MR_KBSR {
+            self.status = (self.status & KBSR_READY) | (value & !KBSR_READY);
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self, console: &mut dyn Console) -> Result<(), VmError> {
+        // Without interrupts, the keys are only taken when the program asks for them
+        if self.status & KBSR_INTERRUPT_ENABLE != 0 {
+            self.poll(console)?;
+        }
+        Ok(())
+    }
+
+    fn interrupt(&self) -> Option<Interrupt> {
+        let request = KBSR_READY | KBSR_INTERRUPT_ENABLE;
+        if self.status & request == request {
+            Some(Interrupt {
+                vector: KEYBOARD_INTERRUPT_VECTOR,
+                priority: KEYBOARD_PRIORITY,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Keyboard, KBSR_INTERRUPT_ENABLE, KEYBOARD_INTERRUPT_VECTOR};
+    use crate::hardware::{console::BufferConsole, consts, devices::Device};
+
+    #[test]
+    fn test_keyboard_status_is_ready_when_there_is_a_key() {
+        let mut keyboard = Keyboard::new();
+        let mut console = BufferConsole::new(b"a");
+
+        assert_eq!(
+            1 << 15,
+            keyboard.read(consts::MR_KBSR, &mut console).unwrap()
+        );
+        assert_eq!(
+            b'a' as u16,
+            keyboard.read(consts::MR_KBDR, &mut console).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_keyboard_status_is_not_ready_without_keys() {
+        let mut keyboard = Keyboard::new();
+        let mut console = BufferConsole::new(b"");
+
+        assert_eq!(0, keyboard.read(consts::MR_KBSR, &mut console).unwrap());
+    }
+
+    #[test]
+    fn test_keyboard_status_stays_ready_until_the_data_is_read() {
+        // A second key is not taken until the first one is read
+        let mut keyboard = Keyboard::new();
+        let mut console = BufferConsole::new(b"ab");
+
+        keyboard.read(consts::MR_KBSR, &mut console).unwrap();
+        assert_eq!(
+            1 << 15,
+            keyboard.read(consts::MR_KBSR, &mut console).unwrap()
+        );
+        assert_eq!(
+            b'a' as u16,
+            keyboard.read(consts::MR_KBDR, &mut console).unwrap()
+        );
+
+        assert_eq!(
+            1 << 15,
+            keyboard.read(consts::MR_KBSR, &mut console).unwrap()
+        );
+        assert_eq!(
+            b'b' as u16,
+            keyboard.read(consts::MR_KBDR, &mut console).unwrap()
+        );
+        assert_eq!(0, keyboard.read(consts::MR_KBSR, &mut console).unwrap());
+    }
+
+    #[test]
+    fn test_keyboard_requests_an_interrupt_when_enabled_and_a_key_is_typed() {
+        let mut keyboard = Keyboard::new();
+        let mut console = BufferConsole::new(b"a");
+
+        keyboard.tick(&mut console).unwrap();
+        assert_eq!(None, keyboard.interrupt());
+
+        keyboard
+            .write(consts::MR_KBSR, KBSR_INTERRUPT_ENABLE, &mut console)
+            .unwrap();
+        keyboard.tick(&mut console).unwrap();
+        assert_eq!(
+            Some(KEYBOARD_INTERRUPT_VECTOR),
+            keyboard.interrupt().map(|interrupt| interrupt.vector)
+        );
+
+        // Reading the key ends the request
+        keyboard.read(consts::MR_KBDR, &mut console).unwrap();
+        assert_eq!(None, keyboard.interrupt());
+    }
+}
@@ -0,0 +1,99 @@
+use crate::{
+    errors::VmError,
+    hardware::{console::Console, consts},
+};
+
+use super::Device;
+
+/// The clock enable bit. The machine stops when a program clears it
+pub const MCR_CLOCK_ENABLE: u16 = 1 << 15;
+
+/// The machine control register (MCR)
+pub struct Mcr {
+    value: u16,
+    halt_requested: bool,
+}
+
+impl Default for Mcr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mcr {
+    pub fn new() -> Self {
+        Mcr {
+            value: MCR_CLOCK_ENABLE,
+            halt_requested: false,
+        }
+    }
+}
+
+impl Device for Mcr {
+    fn read(&mut self, address: u16, _console: &mut dyn Console) -> Result<u16, VmError> {
+        match address {
+            consts::MR_MCR => Ok(self.value),
+            _ => Ok(0),
+        }
+    }
+
+    fn write(
+        &mut self,
+        address: u16,
+        value: u16,
+        _console: &mut dyn Console,
+    ) -> Result<(), VmError> {
+        if address == consts::MR_MCR {
+            self.value = value;
+            if value & MCR_CLOCK_ENABLE == 0 {
+                self.halt_requested = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn take_halt_request(&mut self) -> bool {
+        std::mem::take(&mut self.halt_requested)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mcr, MCR_CLOCK_ENABLE};
+    use crate::hardware::{console::BufferConsole, consts, devices::Device};
+
+    #[test]
+    fn test_mcr_starts_with_the_clock_enabled() {
+        let mut mcr = Mcr::new();
+        let mut console = BufferConsole::new(b"");
+
+        assert_eq!(
+            MCR_CLOCK_ENABLE,
+            mcr.read(consts::MR_MCR, &mut console).unwrap()
+        );
+        assert!(!mcr.take_halt_request());
+    }
+
+    #[test]
+    fn test_mcr_clearing_the_clock_enable_bit_requests_a_halt() {
+        // The request is given only once, the register keeps the value written
+        let mut mcr = Mcr::new();
+        let mut console = BufferConsole::new(b"");
+
+        mcr.write(consts::MR_MCR, 0x7FFF, &mut console).unwrap();
+
+        assert!(mcr.take_halt_request());
+        assert!(!mcr.take_halt_request());
+        assert_eq!(0x7FFF, mcr.read(consts::MR_MCR, &mut console).unwrap());
+    }
+
+    #[test]
+    fn test_mcr_writes_that_keep_the_clock_enabled_do_not_halt() {
+        let mut mcr = Mcr::new();
+        let mut console = BufferConsole::new(b"");
+
+        mcr.write(consts::MR_MCR, 0xFFFF, &mut console).unwrap();
+
+        assert!(!mcr.take_halt_request());
+    }
+}
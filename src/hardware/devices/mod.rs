@@ -0,0 +1,237 @@
+pub mod display;
+pub mod keyboard;
+pub mod mcr;
+pub mod timer;
+
+use crate::errors::VmError;
+
+use super::console::Console;
+
+use std::ops::RangeInclusive;
+
+/// A peripheral whose registers are mapped in the memory. Reads and writes to its addresses reach it instead of the memory
+pub trait Device {
+    /// Returns the value of the register in the given address
+    fn read(&mut self, address: u16, console: &mut dyn Console) -> Result<u16, VmError>;
+
+    /// Sets the value of the register in the given address
+    fn write(&mut self, address: u16, value: u16, console: &mut dyn Console)
+        -> Result<(), VmError>;
+
+    /// Called after every instruction executed, so the device can work even if the program doesn't access it
+    fn tick(&mut self, _console: &mut dyn Console) -> Result<(), VmError> {
+        Ok(())
+    }
+
+    /// Returns the interrupt the device is requesting, if any
+    fn interrupt(&self) -> Option<Interrupt> {
+        None
+    }
+
+    /// Returns true, once, after the device asked to stop the machine
+    fn take_halt_request(&mut self) -> bool {
+        false
+    }
+}
+
+/// An interrupt request: the entry of the interrupt vector table to use and the priority level of the request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupt {
+    pub vector: u16,
+    pub priority: u16,
+}
+
+/// Keeps the devices registered and the range of addresses each of them answers to
+#[derive(Default)]
+pub struct Bus {
+    devices: Vec<(RangeInclusive<u16>, Box<dyn Device>)>,
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Bus {
+            devices: Vec::new(),
+        }
+    }
+
+    /// Maps the device to the range of addresses. The range can't be shared with another device
+    pub fn register(
+        &mut self,
+        range: RangeInclusive<u16>,
+        device: Box<dyn Device>,
+    ) -> Result<(), VmError> {
+        if let Some((used, _)) = self
+            .devices
+            .iter()
+            .find(|(used, _)| used.start() <= range.end() && range.start() <= used.end())
+        {
+            return Err(VmError::DeviceAddressInUse(
+                *used.start().max(range.start()),
+            ));
+        }
+        self.devices.push((range, device));
+        Ok(())
+    }
+
+    pub fn is_mapped(&self, address: u16) -> bool {
+        self.devices
+            .iter()
+            .any(|(range, _)| range.contains(&address))
+    }
+
+    /// Reads from the device mapped to the address. Returns None if there is no device on it
+    pub fn read(
+        &mut self,
+        address: u16,
+        console: &mut dyn Console,
+    ) -> Result<Option<u16>, VmError> {
+        match self.device_at(address) {
+            Some(device) => Ok(Some(device.read(address, console)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Writes to the device mapped to the address. Returns false if there is no device on it
+    pub fn write(
+        &mut self,
+        address: u16,
+        value: u16,
+        console: &mut dyn Console,
+    ) -> Result<bool, VmError> {
+        match self.device_at(address) {
+            Some(device) => {
+                device.write(address, value, console)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Lets every device do its work for the last instruction executed
+    pub fn tick(&mut self, console: &mut dyn Console) -> Result<(), VmError> {
+        for (_, device) in self.devices.iter_mut() {
+            device.tick(console)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the request with the highest priority among the devices, if any
+    pub fn pending_interrupt(&self) -> Option<Interrupt> {
+        self.devices
+            .iter()
+            .filter_map(|(_, device)| device.interrupt())
+            .max_by_key(|interrupt| interrupt.priority)
+    }
+
+    /// Returns true if any device asked to stop the machine since the last call
+    pub fn take_halt_request(&mut self) -> bool {
+        self.devices
+            .iter_mut()
+            .fold(false, |requested, (_, device)| {
+                device.take_halt_request() || requested
+            })
+    }
+
+    fn device_at(&mut self, address: u16) -> Option<&mut Box<dyn Device>> {
+        self.devices
+            .iter_mut()
+            .find(|(range, _)| range.contains(&address))
+            .map(|(_, device)| device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bus, Device, Interrupt};
+    use crate::{errors::VmError, hardware::console::BufferConsole, hardware::console::Console};
+
+    /// Remembers the last value written
+    struct Latch(u16);
+
+    impl Device for Latch {
+        fn read(&mut self, _address: u16, _console: &mut dyn Console) -> Result<u16, VmError> {
+            Ok(self.0)
+        }
+
+        fn write(
+            &mut self,
+            _address: u16,
+            value: u16,
+            _console: &mut dyn Console,
+        ) -> Result<(), VmError> {
+            self.0 = value;
+            Ok(())
+        }
+    }
+
+    /// Always requests an interrupt with the given priority
+    struct Requester(u16);
+
+    impl Device for Requester {
+        fn read(&mut self, _address: u16, _console: &mut dyn Console) -> Result<u16, VmError> {
+            Ok(0)
+        }
+
+        fn write(
+            &mut self,
+            _address: u16,
+            _value: u16,
+            _console: &mut dyn Console,
+        ) -> Result<(), VmError> {
+            Ok(())
+        }
+
+        fn interrupt(&self) -> Option<Interrupt> {
+            Some(Interrupt {
+                vector: 0x90 + self.0,
+                priority: self.0,
+            })
+        }
+    }
+
+    #[test]
+    fn test_bus_sends_accesses_to_the_device_in_the_range() {
+        let mut bus = Bus::new();
+        let mut console = BufferConsole::new(b"");
+        bus.register(0xFE10..=0xFE11, Box::new(Latch(0))).unwrap();
+
+        assert!(bus.write(0xFE11, 42, &mut console).unwrap());
+        assert_eq!(Some(42), bus.read(0xFE10, &mut console).unwrap());
+    }
+
+    #[test]
+    fn test_bus_without_device_in_the_address_does_nothing() {
+        let mut bus = Bus::new();
+        let mut console = BufferConsole::new(b"");
+        bus.register(0xFE10..=0xFE11, Box::new(Latch(0))).unwrap();
+
+        assert!(!bus.write(0xFE12, 42, &mut console).unwrap());
+        assert_eq!(None, bus.read(0xFE12, &mut console).unwrap());
+    }
+
+    #[test]
+    fn test_bus_does_not_allow_devices_sharing_addresses() {
+        let mut bus = Bus::new();
+        bus.register(0xFE10..=0xFE11, Box::new(Latch(0))).unwrap();
+
+        assert!(bus.register(0xFE11..=0xFE12, Box::new(Latch(0))).is_err());
+    }
+
+    #[test]
+    fn test_bus_pending_interrupt_is_the_one_with_highest_priority() {
+        let mut bus = Bus::new();
+        bus.register(0xFE10..=0xFE10, Box::new(Requester(2)))
+            .unwrap();
+        bus.register(0xFE11..=0xFE11, Box::new(Requester(5)))
+            .unwrap();
+        bus.register(0xFE12..=0xFE12, Box::new(Latch(0))).unwrap();
+
+        assert_eq!(
+            Some(Interrupt {
+                vector: 0x95,
+                priority: 5
+            }),
+            bus.pending_interrupt()
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{
+    errors::VmError,
+    hardware::{console::Console, consts},
+};
+
+use super::{Device, Interrupt};
+
+/// Set in the status register every time the interval elapses. Reading the status clears it
+const TSR_FIRED: u16 = 1 << 15;
+
+/// Set in the status register by the program to get an interrupt when the timer fires
+pub const TSR_INTERRUPT_ENABLE: u16 = 1 << 14;
+
+pub const TIMER_INTERRUPT_VECTOR: u16 = 0x81;
+pub const TIMER_PRIORITY: u16 = 5;
+
+/// A timer that fires every time the number of instructions in the interval register (TIR) is executed. Its status is in the TSR. An interval of 0 stops it
+#[derive(Default)]
+pub struct Timer {
+    status: u16,
+    interval: u16,
+    count: u16,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Timer {
+            status: 0,
+            interval: 0,
+            count: 0,
+        }
+    }
+}
+
+impl Device for Timer {
+    fn read(&mut self, address: u16, _console: &mut dyn Console) -> Result<u16, VmError> {
+        match address {
+            consts::MR_TSR => {
+                let status = self.status;
+                self.status &= !TSR_FIRED;
+                Ok(status)
+            }
+            consts::MR_TIR => Ok(self.interval),
+            _ => Ok(0),
+        }
+    }
+
+    fn write(
+        &mut self,
+        address: u16,
+        value: u16,
+        _console: &mut dyn Console,
+    ) -> Result<(), VmError> {
+        match address {
+            // Only the timer can set the fired bit
+            consts::MR_TSR => {
+                self.status = (self.status & TSR_FIRED) | (value & TSR_INTERRUPT_ENABLE)
+            }
+            // A new interval starts to count from the next instruction
+            consts::MR_TIR => {
+                self.interval = value;
+                self.count = 0;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self, _console: &mut dyn Console) -> Result<(), VmError> {
+        if self.interval == 0 {
+            return Ok(());
+        }
+
+        self.count += 1;
+        if self.count >= self.interval {
+            self.count = 0;
+            self.status |= TSR_FIRED;
+        }
+        Ok(())
+    }
+
+    fn interrupt(&self) -> Option<Interrupt> {
+        let request = TSR_FIRED | TSR_INTERRUPT_ENABLE;
+        if self.status & request == request {
+            Some(Interrupt {
+                vector: TIMER_INTERRUPT_VECTOR,
+                priority: TIMER_PRIORITY,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Timer, TIMER_INTERRUPT_VECTOR, TSR_INTERRUPT_ENABLE};
+    use crate::hardware::{console::BufferConsole, consts, devices::Device};
+
+    #[test]
+    fn test_timer_fires_every_interval() {
+        // The fired bit is set after the third instruction, and cleared when the status is read
+        let mut timer = Timer::new();
+        let mut console = BufferConsole::new(b"");
+        timer.write(consts::MR_TIR, 3, &mut console).unwrap();
+
+        timer.tick(&mut console).unwrap();
+        timer.tick(&mut console).unwrap();
+        assert_eq!(0, timer.read(consts::MR_TSR, &mut console).unwrap());
+
+        timer.tick(&mut console).unwrap();
+        assert_eq!(1 << 15, timer.read(consts::MR_TSR, &mut console).unwrap());
+        assert_eq!(0, timer.read(consts::MR_TSR, &mut console).unwrap());
+    }
+
+    #[test]
+    fn test_timer_without_interval_does_not_fire() {
+        let mut timer = Timer::new();
+        let mut console = BufferConsole::new(b"");
+
+        for _ in 0..100 {
+            timer.tick(&mut console).unwrap();
+        }
+
+        assert_eq!(0, timer.read(consts::MR_TSR, &mut console).unwrap());
+    }
+
+    #[test]
+    fn test_timer_requests_an_interrupt_when_enabled_and_fired() {
+        let mut timer = Timer::new();
+        let mut console = BufferConsole::new(b"");
+        timer.write(consts::MR_TIR, 1, &mut console).unwrap();
+
+        timer.tick(&mut console).unwrap();
+        assert_eq!(None, timer.interrupt());
+
+        timer
+            .write(consts::MR_TSR, TSR_INTERRUPT_ENABLE, &mut console)
+            .unwrap();
+        assert_eq!(
+            Some(TIMER_INTERRUPT_VECTOR),
+            timer.interrupt().map(|interrupt| interrupt.vector)
+        );
+
+        // Reading the status ends the request
+        timer.read(consts::MR_TSR, &mut console).unwrap();
+        assert_eq!(None, timer.interrupt());
+    }
+}
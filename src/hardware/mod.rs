@@ -1,3 +1,5 @@
+pub mod console;
 pub mod consts;
+pub mod devices;
 pub mod opcodes;
 pub mod vm;
@@ -1,11 +1,6 @@
-use super::consts;
+use super::{consts, vm::TrapMode};
 use crate::{errors::VmError, VM};
 
-use std::{
-    io::{self, Read, Write},
-    process,
-};
-
 pub const OP_BR: u16 = 0; /* branch */
 pub const OP_ADD: u16 = 1; /* add  */
 pub const OP_LD: u16 = 2; /* load */
@@ -14,7 +9,7 @@ pub const OP_JSR: u16 = 4; /* jump register */
 pub const OP_AND: u16 = 5; /* bitwise and */
 pub const OP_LDR: u16 = 6; /* load register */
 pub const OP_STR: u16 = 7; /* store register */
-pub const OP_RTI: u16 = 8; /* unused */
+pub const OP_RTI: u16 = 8; /* return from interrupt */
 pub const OP_NOT: u16 = 9; /* bitwise not */
 pub const OP_LDI: u16 = 10; /* load indirect */
 pub const OP_STI: u16 = 11; /* store indirect */
@@ -23,6 +18,28 @@ pub const OP_RES: u16 = 13; /* reserved (unused) */
 pub const OP_LEA: u16 = 14; /* load effective address */
 pub const OP_TRAP: u16 = 15; /* execute trap */
 
+/// Returns the assembly mnemonic of the opcode
+pub fn name(op: u16) -> &'static str {
+    match op {
+        OP_BR => "BR",
+        OP_ADD => "ADD",
+        OP_LD => "LD",
+        OP_ST => "ST",
+        OP_JSR => "JSR",
+        OP_AND => "AND",
+        OP_LDR => "LDR",
+        OP_STR => "STR",
+        OP_RTI => "RTI",
+        OP_NOT => "NOT",
+        OP_LDI => "LDI",
+        OP_STI => "STI",
+        OP_JMP => "JMP",
+        OP_LEA => "LEA",
+        OP_TRAP => "TRAP",
+        _ => "RES",
+    }
+}
+
 pub const TRAP_GETC: u16 = 0x20;
 pub const TRAP_OUT: u16 = 0x21;
 pub const TRAP_PUTS: u16 = 0x22;
@@ -184,7 +201,8 @@ pub fn ldi(instr: u16, vm: &mut VM) -> Result<(), VmError> {
     let pc_offset = sign_extend(instr & 0x1FF, 9);
 
     // add pc_offset to the current PC, look at that memory location to get the final address
-    let address_read = vm.mem_read(vm.get_register_value(consts::RPC)? + pc_offset)?;
+    let address: u32 = vm.get_register_value(consts::RPC)? as u32 + pc_offset as u32;
+    let address_read = vm.mem_read(address as u16)?;
     let value = vm.mem_read(address_read)?;
 
     vm.update_register_value(r0, value)?;
@@ -270,7 +288,7 @@ pub fn st(instr: u16, vm: &mut VM) -> Result<(), VmError> {
 
     let value = vm.get_register_value(source_reg)?;
 
-    vm.mem_write(address, value);
+    vm.mem_write(address, value)?;
 
     Ok(())
 }
@@ -293,7 +311,7 @@ pub fn sti(instr: u16, vm: &mut VM) -> Result<(), VmError> {
 
     // store the reg value to the adress read above
     let value = vm.get_register_value(source_reg)?;
-    vm.mem_write(address as u16, value);
+    vm.mem_write(address as u16, value)?;
 
     Ok(())
 }
@@ -317,37 +335,70 @@ pub fn str(instr: u16, vm: &mut VM) -> Result<(), VmError> {
 
     let value = vm.get_register_value(dest_reg)?;
 
-    vm.mem_write(address, value);
+    vm.mem_write(address, value)?;
+
+    Ok(())
+}
+
+// RTI
+
+/// Returns from an interrupt: pops the pc and the psr from the supervisor stack. It can only be used in supervisor mode
+pub fn rti(instr: u16, vm: &mut VM) -> Result<(), VmError> {
+    if vm.is_user_mode() {
+        let pc = vm.get_register_value(consts::RPC)?.wrapping_sub(1);
+        return Err(VmError::PrivilegeViolation { pc, instr });
+    }
+
+    let sp = vm.get_register_value(consts::RR6)?;
+    let pc = vm.mem_read(sp)?;
+    let psr = vm.mem_read(sp.wrapping_add(1))?;
+    vm.update_register_value(consts::RR6, sp.wrapping_add(2))?;
+
+    vm.update_register_value(consts::RPC, pc)?;
+    // Going back to user mode saves the supervisor stack pointer and restores the user one
+    vm.set_psr(psr);
 
     Ok(())
 }
 
 // TRAP
 
-/// Performs the corresponding trap operation
+/// Performs the corresponding trap operation. With vectored traps, jumps to the service routine in the trap vector table instead
 pub fn trap(instr: u16, vm: &mut VM) -> Result<(), VmError> {
     // Set the Reg7 to the PC value
     let pc_value = vm.get_register_value(consts::RPC)?;
     vm.update_register_value(consts::RR7, pc_value)?;
 
+    let table_entry = consts::TRAP_VECTOR_TABLE + (instr & 0xFF);
+    match vm.trap_mode() {
+        TrapMode::Native => {}
+        TrapMode::Vectored => {
+            // The service routine returns to the caller with RET, through R7
+            let routine = vm.mem_read(table_entry)?;
+            return vm.update_register_value(consts::RPC, routine);
+        }
+        TrapMode::Supervisor => {
+            // The service routine runs in supervisor mode, and returns to the caller with RTI
+            return vm.enter_service_routine(table_entry, None);
+        }
+    }
+
     match instr & 0xFF {
         TRAP_GETC => {
             //Read a single character from the keyboard. The character is not echoed onto the
             //console. Its ASCII code is copied into R0. The high eight bits of R0 are cleared.
 
-            let mut buf = [0; 1];
-            if let Err(e) = io::stdin().read_exact(&mut buf) {
-                return Err(VmError::KeyboardInputError(e));
+            match vm.console_mut().read_char()? {
+                Some(c) => vm.update_register_value(consts::RR0, c as u16)?,
+                None => return vm.wait_for_input(),
             }
-
-            vm.update_register_value(consts::RR0, buf[0] as u16)?;
         }
         TRAP_OUT => {
             //Write a character in R0 to the console display.
 
             let c = vm.get_register_value(consts::RR0)? as u8;
-            print!("{}", c as char);
-            io::stdout().flush().expect("failed to flush");
+            vm.console_mut().write_char(c)?;
+            vm.console_mut().flush()?;
         }
         TRAP_PUTS => {
             // Write a string of ASCII characters to the console display.
@@ -355,34 +406,40 @@ pub fn trap(instr: u16, vm: &mut VM) -> Result<(), VmError> {
             let mut index = vm.get_register_value(consts::RR0)?;
             let mut c = vm.mem_read(index)?;
 
-            // 0x0000 is a the NULL character equivalent
+            // 0x0000 is a the NULL character equivalent. Without it, the string ends at the end of the memory
             while c != 0x0000 {
-                print!("{}", (c as u8) as char);
-                index += 1;
+                vm.console_mut().write_char(c as u8)?;
+                let Some(next) = index.checked_add(1) else {
+                    break;
+                };
+                index = next;
                 c = vm.mem_read(index)?;
             }
-            io::stdout().flush().expect("failed to flush");
+            vm.console_mut().flush()?;
         }
         TRAP_IN => {
             //Print a prompt on the screen and read a single character from the keyboard. The
             //character is echoed onto the console monitor.
 
-            println!("Enter a character: ");
-
-            let mut buf: [u8; 1] = [0; 1];
-            if let Err(e) = io::stdin().read_exact(&mut buf) {
-                return Err(VmError::KeyboardInputError(e));
+            // When the instruction is executed again after waiting for a key, the prompt is already there
+            if !vm.is_retrying_input() {
+                write_str(vm, "Enter a character: \n")?;
+                vm.console_mut().flush()?;
             }
 
-            let c = buf[0];
-            print!("{}", c as char);
-            io::stdout().flush().expect("failed to flush");
+            let c = match vm.console_mut().read_char()? {
+                Some(c) => c,
+                None => return vm.wait_for_input(),
+            };
+
+            vm.console_mut().write_char(c)?;
+            vm.console_mut().flush()?;
 
             vm.update_register_value(consts::RR0, c as u16)?;
             vm.update_flags(consts::RR0)?;
         }
         TRAP_PUTSP => {
-            // Write a string of ASCII characters to the console in parts (first half, second half)
+            // Write a string of ASCII characters to the console in parts (first half, second half). Like PUTS, it ends at the end of the memory
 
             let mut index = vm.get_register_value(consts::RR0)?;
             let mut c = vm.mem_read(index)?;
@@ -390,37 +447,53 @@ pub fn trap(instr: u16, vm: &mut VM) -> Result<(), VmError> {
             // 0x0000 is a the NULL character equivalent
             while c != 0x0000 {
                 let char_1 = c & 0xFF;
-                print!("{}", (char_1 as u8) as char);
+                vm.console_mut().write_char(char_1 as u8)?;
                 let char_2 = c >> 8;
                 if char_2 != 0x0000 {
-                    print!("{}", (char_2 as u8) as char);
+                    vm.console_mut().write_char(char_2 as u8)?;
                 }
-                index += 1;
+                let Some(next) = index.checked_add(1) else {
+                    break;
+                };
+                index = next;
                 c = vm.mem_read(index)?;
             }
-            io::stdout().flush().expect("failed to flush");
+            vm.console_mut().flush()?;
         }
         TRAP_HALT => {
             // Stop the program
-            println!("HALT detected");
-            io::stdout().flush().expect("failed to flush");
-            process::exit(1);
+            write_str(vm, "HALT detected\n")?;
+            vm.console_mut().flush()?;
+            vm.halt();
         }
         _ => {
-            // Should not reach this point
-            process::exit(1);
+            let pc = pc_value.wrapping_sub(1);
+            return Err(VmError::UnknownTrapVector { pc, instr });
         }
     }
 
     Ok(())
 }
 
+/// Writes every character of the string in the console
+fn write_str(vm: &mut VM, s: &str) -> Result<(), VmError> {
+    for c in s.bytes() {
+        vm.console_mut().write_char(c)?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{add, and, br, jmp, jsr, ld, ldi, ldr, lea, not, st, sti, str, trap, TRAP_OUT};
+    use super::{
+        add, and, br, jmp, jsr, ld, ldi, ldr, lea, not, rti, st, sti, str, trap, TRAP_GETC,
+        TRAP_HALT, TRAP_OUT, TRAP_PUTS, TRAP_PUTSP,
+    };
 
+    use crate::errors::VmError;
+    use crate::hardware::console::BufferConsole;
     use crate::hardware::consts;
-    use crate::hardware::vm::VM;
+    use crate::hardware::vm::{StepOutcome, TrapMode, VM};
 
     // ADD
 
@@ -784,8 +857,7 @@ mod tests {
         // When putting a negative value, ld sets negative flag on
 
         let mut vm = VM::new();
-        vm.update_register_value(consts::RR1, u16::max_value())
-            .unwrap();
+        vm.update_register_value(consts::RR1, u16::MAX).unwrap();
 
         // This means 'Put at offset direction of memory the content of the source register'
         let st_instr: u16 = 0b0011001000000001;
@@ -826,6 +898,22 @@ mod tests {
         assert!(vm.get_register_value(consts::RCOND).unwrap() == consts::FL_POS);
     }
 
+    #[test]
+    fn test_ldi_with_negative_offset_reads_behind_the_pc() {
+        // A negative offset takes the pointer from an address before the pc
+
+        let mut vm = VM::new();
+        vm.update_register_value(consts::RPC, 0x3010).unwrap();
+        vm.mem_write(0x300F, 0x4000).unwrap();
+        vm.mem_write(0x4000, 42).unwrap();
+
+        // This means 'Put at RR3 the content of the address stored in pc-1'
+        let ldi_instr: u16 = 0b1010011111111111;
+        ldi(ldi_instr, &mut vm).unwrap();
+
+        assert_eq!(42, vm.get_register_value(consts::RR3).unwrap());
+    }
+
     #[test]
     fn test_ldi_sets_zero_flag_on_when_putting_zero_values() {
         // When putting a zero value, ldi sets zero flag on (values of memory are initialized in zero)
@@ -902,8 +990,7 @@ mod tests {
         // Not puts in a destination register the result of the not operation on the base register
 
         let mut vm = VM::new();
-        vm.update_register_value(consts::RR1, u16::max_value())
-            .unwrap();
+        vm.update_register_value(consts::RR1, u16::MAX).unwrap();
         vm.update_register_value(consts::RR2, 5).unwrap();
 
         // This means 'Put in the destination register the result of the not operation on the base register'
@@ -935,7 +1022,7 @@ mod tests {
         // When performing with a 'negative' number, sets the positive flag on
 
         let mut vm = VM::new();
-        vm.update_register_value(consts::RR1, u16::max_value() - 10)
+        vm.update_register_value(consts::RR1, u16::MAX - 10)
             .unwrap();
 
         // This means 'Put in the destination register the result of the not operation on the base register'
@@ -1016,6 +1103,47 @@ mod tests {
         assert_eq!(57, vm.get_register_value(consts::RR3).unwrap());
     }
 
+    // RTI
+
+    #[test]
+    fn test_rti_pops_pc_and_psr_and_goes_back_to_user_mode() {
+        // The supervisor stack has the pc on top and the psr below it
+        let mut vm = VM::new();
+        vm.update_register_value(consts::RR6, 0xF000).unwrap();
+        vm.set_psr(0x0000);
+        vm.update_register_value(consts::RR6, 0x2FFE).unwrap();
+        vm.mem_write(0x2FFE, 0x3010).unwrap();
+        vm.mem_write(0x2FFF, consts::PSR_USER | consts::FL_NEG)
+            .unwrap();
+
+        rti(0x8000, &mut vm).unwrap();
+
+        assert_eq!(0x3010, vm.get_register_value(consts::RPC).unwrap());
+        assert!(vm.is_user_mode());
+        assert_eq!(
+            consts::FL_NEG,
+            vm.get_register_value(consts::RCOND).unwrap()
+        );
+        assert_eq!(0xF000, vm.get_register_value(consts::RR6).unwrap());
+        assert_eq!(0x3000, vm.saved_ssp());
+    }
+
+    #[test]
+    fn test_rti_in_user_mode_is_a_privilege_violation() {
+        let mut vm = VM::new();
+        vm.update_register_value(consts::RPC, 0x3001).unwrap();
+
+        let result = rti(0x8000, &mut vm);
+
+        assert!(matches!(
+            result,
+            Err(VmError::PrivilegeViolation {
+                pc: 0x3000,
+                instr: 0x8000
+            })
+        ));
+    }
+
     // TRAP
 
     #[test]
@@ -1033,4 +1161,221 @@ mod tests {
 
         assert_eq!(16, vm.get_register_value(consts::RR7).unwrap());
     }
+
+    #[test]
+    fn test_trap_out_writes_r0_in_the_console() {
+        let console = BufferConsole::new(b"");
+        let output = console.output();
+        let mut vm = VM::with_console(Box::new(console));
+        vm.update_register_value(consts::RR0, b'k' as u16).unwrap();
+
+        trap(TRAP_OUT, &mut vm).unwrap();
+
+        assert_eq!(b"k".to_vec(), *output.borrow());
+    }
+
+    #[test]
+    fn test_trap_puts_writes_the_string_pointed_by_r0() {
+        // The string goes from the address in R0 to the first NULL character
+        let console = BufferConsole::new(b"");
+        let output = console.output();
+        let mut vm = VM::with_console(Box::new(console));
+        vm.mem_write(0x4000, b'h' as u16).unwrap();
+        vm.mem_write(0x4001, b'i' as u16).unwrap();
+        vm.update_register_value(consts::RR0, 0x4000).unwrap();
+
+        trap(TRAP_PUTS, &mut vm).unwrap();
+
+        assert_eq!(b"hi".to_vec(), *output.borrow());
+    }
+
+    #[test]
+    fn test_trap_putsp_writes_two_characters_per_word() {
+        // The low byte goes first
+        let console = BufferConsole::new(b"");
+        let output = console.output();
+        let mut vm = VM::with_console(Box::new(console));
+        vm.mem_write(0x4000, ((b'i' as u16) << 8) | b'h' as u16)
+            .unwrap();
+        vm.mem_write(0x4001, b'!' as u16).unwrap();
+        vm.update_register_value(consts::RR0, 0x4000).unwrap();
+
+        trap(TRAP_PUTSP, &mut vm).unwrap();
+
+        assert_eq!(b"hi!".to_vec(), *output.borrow());
+    }
+
+    #[test]
+    fn test_trap_getc_puts_the_key_in_r0() {
+        let mut vm = VM::with_console(Box::new(BufferConsole::new(b"q")));
+
+        trap(TRAP_GETC, &mut vm).unwrap();
+
+        assert_eq!(b'q' as u16, vm.get_register_value(consts::RR0).unwrap());
+    }
+
+    #[test]
+    fn test_trap_getc_without_keys_moves_pc_back_to_wait_for_input() {
+        let mut vm = VM::with_console(Box::new(BufferConsole::new(b"")));
+        vm.update_register_value(consts::RPC, 0x3001).unwrap();
+
+        trap(TRAP_GETC, &mut vm).unwrap();
+
+        assert_eq!(0x3000, vm.get_register_value(consts::RPC).unwrap());
+    }
+
+    #[test]
+    fn test_trap_in_shows_the_prompt_once_while_waiting_for_input() {
+        // 'IN' steps twice without keys, and then the key arrives in a new console
+        let console = BufferConsole::new(b"");
+        let waiting_output = console.output();
+        let mut vm = VM::with_console(Box::new(console));
+        vm.update_register_value(consts::RPC, 0x3000).unwrap();
+        vm.mem_write(0x3000, 0xF023).unwrap();
+
+        assert!(matches!(vm.step(), Ok(StepOutcome::WaitingForInput)));
+        assert!(matches!(vm.step(), Ok(StepOutcome::WaitingForInput)));
+        let console = BufferConsole::new(b"k");
+        let output = console.output();
+        vm.set_console(Box::new(console));
+        assert!(matches!(vm.step(), Ok(StepOutcome::Trap(0x23))));
+
+        assert_eq!(b"Enter a character: \n".to_vec(), *waiting_output.borrow());
+        assert_eq!(b"k".to_vec(), *output.borrow());
+        assert_eq!(b'k' as u16, vm.get_register_value(consts::RR0).unwrap());
+    }
+
+    #[test]
+    fn test_trap_puts_with_a_string_at_the_end_of_the_memory() {
+        // The string has no null character, xFFFF is the last one
+        let console = BufferConsole::new(b"");
+        let output = console.output();
+        let mut vm = VM::with_console(Box::new(console));
+        vm.update_register_value(consts::RR0, 0xFFFF).unwrap();
+        vm.mem_write(0xFFFF, b'z' as u16).unwrap();
+
+        trap(TRAP_PUTS, &mut vm).unwrap();
+
+        assert_eq!(b"z".to_vec(), *output.borrow());
+    }
+
+    #[test]
+    fn test_trap_putsp_with_a_string_at_the_end_of_the_memory() {
+        let console = BufferConsole::new(b"");
+        let output = console.output();
+        let mut vm = VM::with_console(Box::new(console));
+        vm.update_register_value(consts::RR0, 0xFFFF).unwrap();
+        vm.mem_write(0xFFFF, (b'x' as u16) << 8 | b'y' as u16)
+            .unwrap();
+
+        trap(TRAP_PUTSP, &mut vm).unwrap();
+
+        assert_eq!(b"yx".to_vec(), *output.borrow());
+    }
+
+    #[test]
+    fn test_trap_halt_stops_the_vm() {
+        let console = BufferConsole::new(b"");
+        let output = console.output();
+        let mut vm = VM::with_console(Box::new(console));
+
+        trap(TRAP_HALT, &mut vm).unwrap();
+
+        assert!(vm.is_halted());
+        assert_eq!(b"HALT detected\n".to_vec(), *output.borrow());
+    }
+
+    #[test]
+    fn test_trap_with_unknown_vector_is_an_error() {
+        // The error has the address of the trap instruction
+        let mut vm = VM::new();
+        vm.update_register_value(consts::RPC, 0x3001).unwrap();
+
+        let result = trap(0xF0FF, &mut vm);
+
+        assert!(matches!(
+            result,
+            Err(VmError::UnknownTrapVector {
+                pc: 0x3000,
+                instr: 0xF0FF
+            })
+        ));
+    }
+
+    #[test]
+    fn test_vectored_trap_jumps_to_the_routine_in_the_trap_vector_table() {
+        // Nothing is written in the console, the routine at x0500 is in charge
+        let console = BufferConsole::new(b"");
+        let output = console.output();
+        let mut vm = VM::with_console(Box::new(console));
+        vm.set_trap_mode(TrapMode::Vectored);
+        vm.update_register_value(consts::RPC, 0x3001).unwrap();
+        vm.mem_write(TRAP_OUT, 0x0500).unwrap();
+
+        trap(0xF000 | TRAP_OUT, &mut vm).unwrap();
+
+        assert_eq!(0x0500, vm.get_register_value(consts::RPC).unwrap());
+        assert_eq!(0x3001, vm.get_register_value(consts::RR7).unwrap());
+        assert!(vm.is_user_mode());
+        assert!(output.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_ret_returns_from_a_vectored_trap() {
+        // The routine at x0500 is just a 'RET', the stack and the mode are left as they were
+        let mut vm = VM::new();
+        vm.set_trap_mode(TrapMode::Vectored);
+        vm.update_register_value(consts::RPC, 0x3001).unwrap();
+        vm.update_register_value(consts::RR6, 0xFE00).unwrap();
+        vm.mem_write(TRAP_OUT, 0x0500).unwrap();
+        vm.mem_write(0x0500, 0b1100000111000000).unwrap();
+
+        trap(0xF000 | TRAP_OUT, &mut vm).unwrap();
+        vm.step().unwrap();
+
+        assert_eq!(0x3001, vm.get_register_value(consts::RPC).unwrap());
+        assert_eq!(0xFE00, vm.get_register_value(consts::RR6).unwrap());
+        assert!(vm.is_user_mode());
+    }
+
+    #[test]
+    fn test_supervisor_trap_runs_the_routine_in_supervisor_mode() {
+        // The psr and the pc go to the supervisor stack
+        let mut vm = VM::new();
+        vm.set_trap_mode(TrapMode::Supervisor);
+        vm.update_register_value(consts::RPC, 0x3001).unwrap();
+        vm.mem_write(TRAP_OUT, 0x0500).unwrap();
+
+        trap(0xF000 | TRAP_OUT, &mut vm).unwrap();
+
+        assert_eq!(0x0500, vm.get_register_value(consts::RPC).unwrap());
+        assert_eq!(0x3001, vm.get_register_value(consts::RR7).unwrap());
+        assert!(!vm.is_user_mode());
+        assert_eq!(0x3001, vm.mem_read(consts::SSP_START - 2).unwrap());
+    }
+
+    #[test]
+    fn test_vectored_trap_with_custom_vector_is_not_an_error() {
+        let mut vm = VM::new();
+        vm.set_trap_mode(TrapMode::Vectored);
+        vm.mem_write(0x0040, 0x0600).unwrap();
+
+        trap(0xF040, &mut vm).unwrap();
+
+        assert_eq!(0x0600, vm.get_register_value(consts::RPC).unwrap());
+    }
+
+    #[test]
+    fn test_rti_returns_from_a_supervisor_trap() {
+        let mut vm = VM::new();
+        vm.set_trap_mode(TrapMode::Supervisor);
+        vm.update_register_value(consts::RPC, 0x3001).unwrap();
+        vm.mem_write(TRAP_OUT, 0x0500).unwrap();
+
+        trap(0xF000 | TRAP_OUT, &mut vm).unwrap();
+        rti(0x8000, &mut vm).unwrap();
+
+        assert_eq!(0x3001, vm.get_register_value(consts::RPC).unwrap());
+        assert!(vm.is_user_mode());
+    }
 }
@@ -1,12 +1,63 @@
-use crate::errors::VmError;
+use crate::{errors::VmError, symbols::SymbolTable};
 
-use super::consts;
+use super::{
+    console::{Console, StdConsole},
+    consts,
+    devices::{display::Display, keyboard::Keyboard, mcr::Mcr, timer::Timer, Bus, Device},
+    opcodes,
+};
 
-use std::io::Read;
+use std::{io::Write, ops::RangeInclusive};
+
+/// What happened when the vm was asked to execute a single instruction
+#[derive(Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// An instruction with the given opcode was executed
+    Executed(u16),
+    /// A trap routine with the given vector was executed
+    Trap(u16),
+    /// An interrupt with the given vector was taken instead of executing an instruction
+    Interrupt(u16),
+    /// The instruction raised the exception with the given vector, and the vm jumped to its handler
+    Exception(u16),
+    /// The machine is stopped, so nothing was executed
+    Halted,
+    /// The instruction needs a key that is not available yet. The pc is left on it, so the next step retries it
+    WaitingForInput,
+    /// The pc reached the breakpoint in the given address, so nothing was executed. The next step executes the instruction
+    Breakpoint(u16),
+}
+
+/// How the TRAP instruction reaches the service routines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapMode {
+    /// The standard routines (GETC, OUT, PUTS, IN, PUTSP and HALT) are done by the vm itself
+    Native,
+    /// Saves the pc in R7 and jumps to the routine in the trap vector table (x0000-x00FF). The routines return with RET
+    Vectored,
+    /// Like the LC-3 of the third edition: also saves the psr and the pc in the supervisor stack, and runs the routine in supervisor mode. The routines return with RTI
+    Supervisor,
+}
 
 pub struct VM {
     memory: [u16; consts::MEMORY_MAX],
     regs: [u16; 11],
+    psr: u16,
+    saved_ssp: u16,
+    saved_usp: u16,
+    console: Box<dyn Console>,
+    bus: Bus,
+    trace: Option<Box<dyn Write>>,
+    symbols: SymbolTable,
+    breakpoints: Vec<u16>,
+    at_breakpoint: bool,
+    trap_mode: TrapMode,
+    vectored_exceptions: bool,
+    access_control: bool,
+    waiting_for_input: bool,
+    /// The instruction in the pc waited for a key, and it is executed again
+    retrying_input: bool,
+    halted: bool,
 }
 
 impl Default for VM {
@@ -16,41 +67,345 @@ impl Default for VM {
 }
 
 impl VM {
+    /// Creates a vm that uses the stdin and the stdout as console
     pub fn new() -> Self {
+        Self::with_console(Box::new(StdConsole))
+    }
+
+    /// Creates a vm with the given console, and the keyboard, the display, the timer and the machine control register in the bus
+    pub fn with_console(console: Box<dyn Console>) -> Self {
         let memory: [u16; consts::MEMORY_MAX] = [0; consts::MEMORY_MAX];
         let regs: [u16; 11] = [0; 11];
-        VM { memory, regs }
+
+        let mut bus = Bus::new();
+        bus.register(consts::MR_KBSR..=consts::MR_KBDR, Box::new(Keyboard::new()))
+            .expect("The bus of a new vm has no devices");
+        bus.register(consts::MR_DSR..=consts::MR_DDR, Box::new(Display::new()))
+            .expect("The bus of a new vm has no devices");
+        bus.register(consts::MR_TSR..=consts::MR_TIR, Box::new(Timer::new()))
+            .expect("The bus of a new vm has no devices");
+        bus.register(consts::MR_MCR..=consts::MR_MCR, Box::new(Mcr::new()))
+            .expect("The bus of a new vm has no devices");
+
+        VM {
+            memory,
+            regs,
+            psr: consts::PSR_USER,
+            saved_ssp: consts::SSP_START,
+            saved_usp: 0,
+            console,
+            bus,
+            trace: None,
+            symbols: SymbolTable::new(),
+            breakpoints: Vec::new(),
+            at_breakpoint: false,
+            trap_mode: TrapMode::Native,
+            vectored_exceptions: false,
+            access_control: false,
+            waiting_for_input: false,
+            retrying_input: false,
+            halted: false,
+        }
     }
 
-    /// There is no way to write in a forbidden address since it's limited by the u16 limits
-    pub fn mem_write(&mut self, address: u16, value: u16) {
-        self.memory[address as usize] = value;
+    /// Maps a device to a range of addresses, so reads and writes on them reach the device instead of the memory
+    pub fn register_device(
+        &mut self,
+        range: RangeInclusive<u16>,
+        device: Box<dyn Device>,
+    ) -> Result<(), VmError> {
+        self.bus.register(range, device)
     }
 
-    /// There is no way to access to a forbidden address since it's limited by the u16 limits
-    pub fn mem_read(&mut self, address: u16) -> Result<u16, VmError> {
-        if address == consts::MR_KBSR {
-            self.handle_keyboard()?;
+    pub fn set_console(&mut self, console: Box<dyn Console>) {
+        self.console = console;
+    }
+
+    pub fn console_mut(&mut self) -> &mut dyn Console {
+        self.console.as_mut()
+    }
+
+    /// Sets where to write a line for every instruction executed, with its address, its encoding and the registers after it. None disables the trace
+    pub fn set_trace(&mut self, trace: Option<Box<dyn Write>>) {
+        self.trace = trace;
+    }
+
+    /// Sets the labels of the programs loaded, which are shown with the addresses in the trace
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Makes the vm stop before executing the instruction in the address
+    pub fn add_breakpoint(&mut self, address: u16) {
+        if !self.breakpoints.contains(&address) {
+            self.breakpoints.push(address);
         }
-        Ok(self.memory[address as usize])
     }
 
-    fn handle_keyboard(&mut self) -> Result<(), VmError> {
-        let mut buffer = [0; 1];
-        match std::io::stdin().read_exact(&mut buffer) {
-            Ok(_) => {
-                if buffer[0] != 0 {
-                    self.mem_write(consts::MR_KBSR, 1 << 15);
-                    self.mem_write(consts::MR_KBDR, buffer[0] as u16);
-                } else {
-                    self.mem_write(consts::MR_KBSR, 0)
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.breakpoints.retain(|breakpoint| *breakpoint != address);
+    }
+
+    pub fn set_trap_mode(&mut self, mode: TrapMode) {
+        self.trap_mode = mode;
+    }
+
+    pub fn trap_mode(&self) -> TrapMode {
+        self.trap_mode
+    }
+
+    /// When enabled, exceptions jump to their handler in the interrupt vector table, as they do with an operating system loaded. Otherwise they are returned as errors
+    pub fn set_vectored_exceptions(&mut self, enabled: bool) {
+        self.vectored_exceptions = enabled;
+    }
+
+    /// When enabled, accesses to the system space (x0000-x2FFF) and to the device registers (xFE00-xFFFF) in user mode are access control violations
+    pub fn set_access_control(&mut self, enabled: bool) {
+        self.access_control = enabled;
+    }
+
+    /// Fetches the instruction pointed by the pc, increments the pc and executes the instruction
+    pub fn step(&mut self) -> Result<StepOutcome, VmError> {
+        let pc = self.get_register_value(consts::RPC)?;
+        if self.halted {
+            return Ok(StepOutcome::Halted);
+        }
+
+        // After stopping on a breakpoint, the next step goes on with the instruction
+        if !self.at_breakpoint && self.breakpoints.contains(&pc) {
+            self.at_breakpoint = true;
+            return Ok(StepOutcome::Breakpoint(pc));
+        }
+        self.at_breakpoint = false;
+
+        // Interrupts are taken only if their priority is higher than the one of the running program
+        if let Some(interrupt) = self.bus.pending_interrupt() {
+            if interrupt.priority > self.priority() {
+                self.enter_service_routine(
+                    consts::INTERRUPT_VECTOR_TABLE + interrupt.vector,
+                    Some(interrupt.priority),
+                )?;
+                return Ok(StepOutcome::Interrupt(interrupt.vector));
+            }
+        }
+
+        // The pc is incremented before the fetch, so an access violation in the fetch is reported like the ones in the execution
+        self.update_register_value(consts::RPC, pc.wrapping_add(1))?;
+
+        let instr = match self.fetch_and_execute(pc) {
+            Ok(instr) => instr,
+            Err(e) => match e.exception_vector() {
+                Some(vector) if self.vectored_exceptions => {
+                    self.enter_service_routine(consts::INTERRUPT_VECTOR_TABLE + vector, None)?;
+                    return Ok(StepOutcome::Exception(vector));
                 }
-                Ok(())
+                _ => return Err(e),
+            },
+        };
+        self.bus.tick(self.console.as_mut())?;
+
+        // Clearing the clock enable bit of the MCR stops the machine after the instruction
+        if self.bus.take_halt_request() {
+            self.halted = true;
+        }
+
+        if self.trace.is_some() {
+            self.write_trace(pc, instr);
+        }
+
+        if self.halted {
+            return Ok(StepOutcome::Halted);
+        }
+        if self.waiting_for_input {
+            self.waiting_for_input = false;
+            return Ok(StepOutcome::WaitingForInput);
+        }
+        self.retrying_input = false;
+
+        let op = instr >> 12;
+        if op == opcodes::OP_TRAP {
+            Ok(StepOutcome::Trap(instr & 0xFF))
+        } else {
+            Ok(StepOutcome::Executed(op))
+        }
+    }
+
+    fn fetch_and_execute(&mut self, pc: u16) -> Result<u16, VmError> {
+        let instr = self.mem_read(pc)?;
+        crate::execute_instruction(instr, self)?;
+        Ok(instr)
+    }
+
+    fn write_trace(&mut self, pc: u16, instr: u16) {
+        let line = format!(
+            "{}: x{:04X} {:<4} {}",
+            self.symbols.describe(pc),
+            instr,
+            opcodes::name(instr >> 12),
+            self.dump_registers()
+        );
+
+        if let Some(trace) = self.trace.as_mut() {
+            // A failure writing the trace should not stop the program
+            let _ = writeln!(trace, "{}", line);
+        }
+    }
+
+    /// Returns the general purpose registers and the psr in a line, like R0=x0000 ... R7=x0000 PSR=x8002
+    pub fn dump_registers(&self) -> String {
+        let mut line = String::new();
+        for (number, value) in self.regs[..consts::RR7 as usize + 1].iter().enumerate() {
+            line.push_str(&format!("R{}=x{:04X} ", number, value));
+        }
+        line.push_str(&format!("PSR=x{:04X}", self.get_psr()));
+        line
+    }
+
+    /// Saves the psr and the pc in the supervisor stack, switches to supervisor mode and jumps to the routine in the given entry of a vector table. The priority level changes only if one is given
+    pub fn enter_service_routine(
+        &mut self,
+        table_entry: u16,
+        priority: Option<u16>,
+    ) -> Result<(), VmError> {
+        let psr = self.get_psr();
+        let pc = self.get_register_value(consts::RPC)?;
+
+        let priority = match priority {
+            Some(priority) => (priority << 8) & consts::PSR_PRIORITY,
+            None => psr & consts::PSR_PRIORITY,
+        };
+        // Entering supervisor mode leaves the supervisor stack in R6
+        self.set_psr((psr & consts::PSR_COND) | priority);
+
+        let sp = self.get_register_value(consts::RR6)?.wrapping_sub(1);
+        self.mem_write(sp, psr)?;
+        let sp = sp.wrapping_sub(1);
+        self.mem_write(sp, pc)?;
+        self.update_register_value(consts::RR6, sp)?;
+
+        let routine = self.mem_read(table_entry)?;
+        self.update_register_value(consts::RPC, routine)
+    }
+
+    /// Stops the machine. No more instructions are executed until it is resumed
+    pub fn halt(&mut self) {
+        self.halted = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.halted = false;
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Called by an instruction that could not get a key. Moves the pc back to it, so it is executed again in the next step
+    pub fn wait_for_input(&mut self) -> Result<(), VmError> {
+        let pc = self.get_register_value(consts::RPC)?;
+        self.update_register_value(consts::RPC, pc.wrapping_sub(1))?;
+        self.waiting_for_input = true;
+        self.retrying_input = true;
+        Ok(())
+    }
+
+    /// Returns true if the instruction being executed is a retry of one that waited for a key
+    pub fn is_retrying_input(&self) -> bool {
+        self.retrying_input
+    }
+
+    /// Writes a word, in the device mapped at the address or else in the memory.
+    /// With access control on, user mode writes outside the user space fail with AccessViolation
+    pub fn mem_write(&mut self, address: u16, value: u16) -> Result<(), VmError> {
+        self.check_access(address)?;
+        if !self.bus.write(address, value, self.console.as_mut())? {
+            self.memory[address as usize] = value;
+        }
+        Ok(())
+    }
+
+    /// Reads a word, from the device mapped at the address or else from the memory.
+    /// With access control on, user mode reads outside the user space fail with AccessViolation
+    pub fn mem_read(&mut self, address: u16) -> Result<u16, VmError> {
+        self.check_access(address)?;
+        match self.bus.read(address, self.console.as_mut())? {
+            Some(value) => Ok(value),
+            None => Ok(self.memory[address as usize]),
+        }
+    }
+
+    /// Writes in the memory without going through the devices nor the access control. Used to load the images
+    pub fn load_word(&mut self, address: u16, value: u16) {
+        self.memory[address as usize] = value;
+    }
+
+    fn check_access(&self, address: u16) -> Result<(), VmError> {
+        let system_address =
+            !(consts::USER_SPACE_START..consts::DEVICE_SPACE_START).contains(&address);
+
+        if self.access_control && self.is_user_mode() && system_address {
+            let pc = self.regs[consts::RPC as usize].wrapping_sub(1);
+            return Err(VmError::AccessViolation { pc, address });
+        }
+        Ok(())
+    }
+
+    /// Returns the Processor Status Register: the privilege in bit 15, the priority level in bits 10-8 and the condition codes in bits 2-0
+    pub fn get_psr(&self) -> u16 {
+        self.psr | (self.regs[consts::RCOND as usize] & consts::PSR_COND)
+    }
+
+    /// Sets the Processor Status Register. When the privilege changes, R6 is swapped between the user and the supervisor stack pointers
+    pub fn set_psr(&mut self, value: u16) {
+        let was_user = self.is_user_mode();
+        self.psr = value & (consts::PSR_USER | consts::PSR_PRIORITY);
+        self.regs[consts::RCOND as usize] = value & consts::PSR_COND;
+
+        let sp = consts::RR6 as usize;
+        match (was_user, self.is_user_mode()) {
+            (false, true) => {
+                self.saved_ssp = self.regs[sp];
+                self.regs[sp] = self.saved_usp;
+            }
+            (true, false) => {
+                self.saved_usp = self.regs[sp];
+                self.regs[sp] = self.saved_ssp;
             }
-            Err(e) => Err(VmError::KeyboardInputError(e)),
+            _ => {}
         }
     }
 
+    pub fn is_user_mode(&self) -> bool {
+        self.psr & consts::PSR_USER != 0
+    }
+
+    /// Returns the priority level, from 0 to 7
+    pub fn priority(&self) -> u16 {
+        (self.psr & consts::PSR_PRIORITY) >> 8
+    }
+
+    /// Returns the supervisor stack pointer saved while running in user mode
+    pub fn saved_ssp(&self) -> u16 {
+        self.saved_ssp
+    }
+
+    pub fn set_saved_ssp(&mut self, value: u16) {
+        self.saved_ssp = value;
+    }
+
+    /// Returns the user stack pointer saved while running in supervisor mode
+    pub fn saved_usp(&self) -> u16 {
+        self.saved_usp
+    }
+
+    pub fn set_saved_usp(&mut self, value: u16) {
+        self.saved_usp = value;
+    }
+
     pub fn update_flags(&mut self, register_number: u16) -> Result<(), VmError> {
         if register_number as usize > self.regs.len() {
             Err(VmError::OutOfBoundsError)
@@ -91,7 +446,20 @@ impl VM {
 
 #[cfg(test)]
 mod tests {
-    use crate::hardware::vm::VM;
+    use crate::errors::VmError;
+    use crate::hardware::{
+        console::BufferConsole,
+        consts,
+        devices::{
+            keyboard::{Keyboard, KBSR_INTERRUPT_ENABLE},
+            timer::TSR_INTERRUPT_ENABLE,
+        },
+        opcodes,
+        vm::{StepOutcome, VM},
+    };
+    use crate::symbols::SymbolTable;
+
+    use std::{cell::RefCell, io::Write, rc::Rc};
 
     #[test]
     fn test_01() {
@@ -109,7 +477,7 @@ mod tests {
 
         let address = 7;
         let value = 18;
-        vm.mem_write(address, value);
+        vm.mem_write(address, value).unwrap();
 
         assert_eq!(value, vm.memory[address as usize]);
     }
@@ -121,7 +489,7 @@ mod tests {
 
         let address = 7;
         let value = 18;
-        vm.mem_write(address, value);
+        vm.mem_write(address, value).unwrap();
 
         let read = vm.mem_read(address).unwrap();
 
@@ -164,4 +532,399 @@ mod tests {
 
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_step_executes_a_single_instruction_and_increments_pc() {
+        // step runs only the instruction pointed by the pc
+        let mut vm = VM::new();
+        vm.update_register_value(consts::RPC, 0x3000).unwrap();
+        vm.update_register_value(consts::RR1, 3).unwrap();
+
+        // 'Add RR1 and an imm5 and put the result on RR3', twice
+        vm.mem_write(0x3000, 0b0001011001100111).unwrap();
+        vm.mem_write(0x3001, 0b0001011001100111).unwrap();
+
+        let outcome = vm.step().unwrap();
+
+        assert_eq!(StepOutcome::Executed(opcodes::OP_ADD), outcome);
+        assert_eq!(10, vm.get_register_value(consts::RR3).unwrap());
+        assert_eq!(0x3001, vm.get_register_value(consts::RPC).unwrap());
+    }
+
+    #[test]
+    fn test_step_reports_the_trap_vector() {
+        // A trap instruction is reported with its vector
+        let mut vm = VM::new();
+        vm.update_register_value(consts::RPC, 0x3000).unwrap();
+        vm.mem_write(0x3000, 0xF000 | opcodes::TRAP_OUT).unwrap();
+
+        let outcome = vm.step().unwrap();
+
+        assert_eq!(StepOutcome::Trap(opcodes::TRAP_OUT), outcome);
+    }
+
+    #[test]
+    fn test_step_at_the_end_of_memory_wraps_the_pc() {
+        // xFFFF is a valid address, and the instruction after it is the one at x0000
+        let mut vm = VM::new();
+        vm.update_register_value(consts::RPC, 0xFFFF).unwrap();
+        vm.mem_write(0xFFFF, 0b0001011001100111).unwrap();
+
+        assert_eq!(StepOutcome::Executed(opcodes::OP_ADD), vm.step().unwrap());
+        assert_eq!(0x0000, vm.get_register_value(consts::RPC).unwrap());
+        assert_eq!(7, vm.get_register_value(consts::RR3).unwrap());
+    }
+
+    #[test]
+    fn test_mem_write_on_the_last_address() {
+        let mut vm = VM::new();
+
+        vm.mem_write(0xFFFF, 0x1234).unwrap();
+
+        assert_eq!(0x1234, vm.mem_read(0xFFFF).unwrap());
+    }
+
+    #[test]
+    fn test_wait_for_input_leaves_pc_on_the_instruction() {
+        // After waiting for input, the instruction is fetched again
+        let mut vm = VM::new();
+        vm.update_register_value(consts::RPC, 0x3001).unwrap();
+
+        vm.wait_for_input().unwrap();
+
+        assert_eq!(0x3000, vm.get_register_value(consts::RPC).unwrap());
+    }
+
+    #[test]
+    fn test_reading_kbsr_takes_a_key_from_the_console() {
+        // When there is a key, kbsr gets the ready bit and kbdr the key
+        let mut vm = VM::with_console(Box::new(BufferConsole::new(b"w")));
+
+        assert_eq!(1 << 15, vm.mem_read(consts::MR_KBSR).unwrap());
+        assert_eq!(b'w' as u16, vm.mem_read(consts::MR_KBDR).unwrap());
+    }
+
+    #[test]
+    fn test_reading_kbsr_without_keys_is_not_ready() {
+        let mut vm = VM::with_console(Box::new(BufferConsole::new(b"")));
+
+        assert_eq!(0, vm.mem_read(consts::MR_KBSR).unwrap());
+    }
+
+    #[test]
+    fn test_writing_ddr_shows_the_character_in_the_console() {
+        // 'Store RR0 in the address pointed by pc+1' where the pointer is DDR
+        let console = BufferConsole::new(b"");
+        let output = console.output();
+        let mut vm = VM::with_console(Box::new(console));
+        vm.update_register_value(consts::RPC, 0x3000).unwrap();
+        vm.update_register_value(consts::RR0, b'!' as u16).unwrap();
+        vm.mem_write(0x3000, 0b1011000000000001).unwrap();
+        vm.mem_write(0x3002, consts::MR_DDR).unwrap();
+
+        vm.step().unwrap();
+
+        assert_eq!(b"!".to_vec(), *output.borrow());
+        assert_eq!(1 << 15, vm.mem_read(consts::MR_DSR).unwrap());
+    }
+
+    #[test]
+    fn test_step_on_a_halted_vm_does_nothing() {
+        let mut vm = VM::new();
+        vm.update_register_value(consts::RPC, 0x3000).unwrap();
+        vm.halt();
+
+        assert_eq!(StepOutcome::Halted, vm.step().unwrap());
+        assert_eq!(0x3000, vm.get_register_value(consts::RPC).unwrap());
+    }
+
+    #[test]
+    fn test_step_on_halt_trap_stops_the_vm() {
+        let mut vm = VM::with_console(Box::new(BufferConsole::new(b"")));
+        vm.update_register_value(consts::RPC, 0x3000).unwrap();
+        vm.mem_write(0x3000, 0xF000 | opcodes::TRAP_HALT).unwrap();
+
+        assert_eq!(StepOutcome::Halted, vm.step().unwrap());
+        assert!(vm.is_halted());
+    }
+
+    #[test]
+    fn test_step_clearing_the_mcr_clock_enable_bit_stops_the_vm() {
+        // 'Store RR0 in the address pointed by pc+1' where the pointer is the MCR, and RR0 has bit 15 clear
+        let mut vm = VM::with_console(Box::new(BufferConsole::new(b"")));
+        vm.update_register_value(consts::RPC, 0x3000).unwrap();
+        vm.mem_write(0x3000, 0b1011000000000001).unwrap();
+        vm.mem_write(0x3002, consts::MR_MCR).unwrap();
+
+        assert_eq!(StepOutcome::Halted, vm.step().unwrap());
+        assert!(vm.is_halted());
+        assert_eq!(0x3001, vm.get_register_value(consts::RPC).unwrap());
+    }
+
+    #[test]
+    fn test_register_device_on_the_keyboard_addresses_is_an_error() {
+        // The keyboard is already on the bus of a new vm
+        let mut vm = VM::new();
+
+        let result =
+            vm.register_device(consts::MR_KBDR..=consts::MR_KBDR, Box::new(Keyboard::new()));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_step_with_trace_writes_the_instruction_executed() {
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = VM::new();
+        vm.set_trace(Some(Box::new(SharedBuffer(Rc::clone(&trace)))));
+        vm.update_register_value(consts::RPC, 0x3000).unwrap();
+        vm.update_register_value(consts::RR1, 3).unwrap();
+
+        // 'Add RR1 and an imm5 and put the result on RR3'
+        vm.mem_write(0x3000, 0b0001011001100111).unwrap();
+        vm.step().unwrap();
+
+        let line = String::from_utf8(trace.borrow().clone()).unwrap();
+        assert!(line.starts_with("x3000: x1667 ADD "));
+        assert!(line.contains(" R3=x000A "));
+    }
+
+    #[test]
+    fn test_step_with_trace_shows_the_labels() {
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = VM::new();
+        let mut symbols = SymbolTable::new();
+        symbols.insert("MAIN", 0x3000);
+        vm.set_symbols(symbols);
+        vm.set_trace(Some(Box::new(SharedBuffer(Rc::clone(&trace)))));
+        vm.update_register_value(consts::RPC, 0x3001).unwrap();
+
+        vm.step().unwrap();
+
+        let line = String::from_utf8(trace.borrow().clone()).unwrap();
+        assert!(line.starts_with("x3001 (MAIN+1): x0000 BR "));
+    }
+
+    #[test]
+    fn test_step_stops_on_a_breakpoint_once() {
+        // The instruction is executed in the step after stopping
+        let mut vm = VM::new();
+        vm.update_register_value(consts::RPC, 0x3000).unwrap();
+        vm.add_breakpoint(0x3001);
+
+        assert_eq!(StepOutcome::Executed(opcodes::OP_BR), vm.step().unwrap());
+        assert_eq!(StepOutcome::Breakpoint(0x3001), vm.step().unwrap());
+        assert_eq!(0x3001, vm.get_register_value(consts::RPC).unwrap());
+        assert_eq!(StepOutcome::Executed(opcodes::OP_BR), vm.step().unwrap());
+        assert_eq!(0x3002, vm.get_register_value(consts::RPC).unwrap());
+    }
+
+    /// Lets the test read what the vm writes in the trace
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_new_vm_starts_in_user_mode_with_priority_zero() {
+        let vm = VM::new();
+
+        assert!(vm.is_user_mode());
+        assert_eq!(0, vm.priority());
+    }
+
+    #[test]
+    fn test_psr_has_the_condition_codes() {
+        // The condition codes are the ones updated by the instructions
+        let mut vm = VM::new();
+        vm.update_register_value(consts::RR1, 0).unwrap();
+        vm.update_flags(consts::RR1).unwrap();
+
+        assert_eq!(consts::PSR_USER | consts::FL_ZRO, vm.get_psr());
+    }
+
+    #[test]
+    fn test_set_psr_to_supervisor_swaps_the_stack_pointers() {
+        // R6 keeps the user stack pointer in user mode, and the supervisor one in supervisor mode
+        let mut vm = VM::new();
+        vm.update_register_value(consts::RR6, 0xF000).unwrap();
+
+        vm.set_psr(0x0401);
+
+        assert!(!vm.is_user_mode());
+        assert_eq!(4, vm.priority());
+        assert_eq!(
+            consts::FL_POS,
+            vm.get_register_value(consts::RCOND).unwrap()
+        );
+        assert_eq!(
+            consts::SSP_START,
+            vm.get_register_value(consts::RR6).unwrap()
+        );
+        assert_eq!(0xF000, vm.saved_usp());
+
+        vm.set_psr(consts::PSR_USER);
+
+        assert_eq!(0xF000, vm.get_register_value(consts::RR6).unwrap());
+        assert_eq!(consts::SSP_START, vm.saved_ssp());
+    }
+
+    #[test]
+    fn test_enter_service_routine_pushes_psr_and_pc_to_the_supervisor_stack() {
+        let mut vm = VM::new();
+        vm.update_register_value(consts::RPC, 0x3005).unwrap();
+        vm.update_register_value(consts::RCOND, consts::FL_POS)
+            .unwrap();
+        vm.mem_write(0x0180, 0x1000).unwrap();
+
+        vm.enter_service_routine(0x0180, Some(4)).unwrap();
+
+        assert!(!vm.is_user_mode());
+        assert_eq!(4, vm.priority());
+        assert_eq!(0x1000, vm.get_register_value(consts::RPC).unwrap());
+        assert_eq!(0x2FFE, vm.get_register_value(consts::RR6).unwrap());
+        assert_eq!(0x3005, vm.mem_read(0x2FFE).unwrap());
+        assert_eq!(
+            consts::PSR_USER | consts::FL_POS,
+            vm.mem_read(0x2FFF).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_step_takes_the_timer_interrupt_when_the_interval_elapses() {
+        // The timer fires after two instructions, and the routine is at x1000
+        let mut vm = VM::with_console(Box::new(BufferConsole::new(b"")));
+        vm.update_register_value(consts::RPC, 0x3000).unwrap();
+        vm.mem_write(0x0181, 0x1000).unwrap();
+        vm.mem_write(consts::MR_TIR, 2).unwrap();
+        vm.mem_write(consts::MR_TSR, TSR_INTERRUPT_ENABLE).unwrap();
+
+        assert_eq!(StepOutcome::Executed(opcodes::OP_BR), vm.step().unwrap());
+        assert_eq!(StepOutcome::Executed(opcodes::OP_BR), vm.step().unwrap());
+        assert_eq!(StepOutcome::Interrupt(0x81), vm.step().unwrap());
+        assert_eq!(0x1000, vm.get_register_value(consts::RPC).unwrap());
+        assert_eq!(5, vm.priority());
+    }
+
+    #[test]
+    fn test_step_takes_the_keyboard_interrupt_and_rti_returns_from_it() {
+        // The service routine at x1000 only has an RTI
+        let mut vm = VM::with_console(Box::new(BufferConsole::new(b"k")));
+        vm.update_register_value(consts::RPC, 0x3000).unwrap();
+        vm.mem_write(0x0180, 0x1000).unwrap();
+        vm.mem_write(0x1000, 0x8000).unwrap();
+        vm.mem_write(consts::MR_KBSR, KBSR_INTERRUPT_ENABLE)
+            .unwrap();
+
+        // Executes the instruction at x3000, and the keyboard sees the key
+        assert_eq!(StepOutcome::Executed(opcodes::OP_BR), vm.step().unwrap());
+        assert_eq!(StepOutcome::Interrupt(0x80), vm.step().unwrap());
+        assert_eq!(0x1000, vm.get_register_value(consts::RPC).unwrap());
+
+        // The routine has the same priority as the keyboard, so it's not interrupted again
+        assert_eq!(StepOutcome::Executed(opcodes::OP_RTI), vm.step().unwrap());
+        assert_eq!(0x3001, vm.get_register_value(consts::RPC).unwrap());
+        assert!(vm.is_user_mode());
+        assert_eq!(0, vm.priority());
+    }
+
+    #[test]
+    fn test_step_with_illegal_opcode_is_an_error_without_vectored_exceptions() {
+        let mut vm = VM::new();
+        vm.update_register_value(consts::RPC, 0x3000).unwrap();
+        vm.mem_write(0x3000, 0xD000).unwrap();
+
+        assert!(vm.step().is_err());
+    }
+
+    #[test]
+    fn test_step_with_illegal_opcode_jumps_to_its_handler_with_vectored_exceptions() {
+        // The handler of the illegal opcode exception is in the entry x0101
+        let mut vm = VM::new();
+        vm.set_vectored_exceptions(true);
+        vm.update_register_value(consts::RPC, 0x3000).unwrap();
+        vm.mem_write(0x3000, 0xD000).unwrap();
+        vm.mem_write(0x0101, 0x1200).unwrap();
+
+        let outcome = vm.step().unwrap();
+
+        assert_eq!(StepOutcome::Exception(consts::EX_ILLEGAL_OPCODE), outcome);
+        assert_eq!(0x1200, vm.get_register_value(consts::RPC).unwrap());
+        assert!(!vm.is_user_mode());
+        assert_eq!(0x3001, vm.mem_read(0x2FFE).unwrap());
+    }
+
+    #[test]
+    fn test_step_with_rti_in_user_mode_jumps_to_the_privilege_violation_handler() {
+        let mut vm = VM::new();
+        vm.set_vectored_exceptions(true);
+        vm.update_register_value(consts::RPC, 0x3000).unwrap();
+        vm.mem_write(0x3000, 0x8000).unwrap();
+        vm.mem_write(0x0100, 0x1100).unwrap();
+
+        let outcome = vm.step().unwrap();
+
+        assert_eq!(StepOutcome::Exception(consts::EX_PRIVILEGE), outcome);
+        assert_eq!(0x1100, vm.get_register_value(consts::RPC).unwrap());
+    }
+
+    #[test]
+    fn test_access_to_system_space_in_user_mode_is_a_violation_with_access_control() {
+        let mut vm = VM::new();
+        vm.set_access_control(true);
+        vm.update_register_value(consts::RPC, 0x3001).unwrap();
+
+        assert!(matches!(
+            vm.mem_write(0x2FFF, 1),
+            Err(VmError::AccessViolation {
+                pc: 0x3000,
+                address: 0x2FFF
+            })
+        ));
+        assert!(vm.mem_read(consts::MR_KBSR).is_err());
+        assert!(vm.mem_read(0x3000).is_ok());
+        assert!(vm.mem_write(0xFDFF, 1).is_ok());
+    }
+
+    #[test]
+    fn test_access_to_system_space_is_allowed_without_access_control() {
+        let mut vm = VM::new();
+
+        assert!(vm.mem_write(0x2FFF, 1).is_ok());
+    }
+
+    #[test]
+    fn test_access_to_system_space_is_allowed_in_supervisor_mode() {
+        let mut vm = VM::new();
+        vm.set_access_control(true);
+        vm.set_psr(0);
+
+        assert!(vm.mem_write(0x2FFF, 1).is_ok());
+    }
+
+    #[test]
+    fn test_step_writing_system_space_jumps_to_the_access_violation_handler() {
+        // 'Put at RR1 + 0 the content of RR2', with RR1 in system space
+        let mut vm = VM::new();
+        vm.set_access_control(true);
+        vm.set_vectored_exceptions(true);
+        vm.load_word(0x0102, 0x1300);
+        vm.load_word(0x3000, 0b0111010001000000);
+        vm.update_register_value(consts::RPC, 0x3000).unwrap();
+        vm.update_register_value(consts::RR1, 0x0200).unwrap();
+        vm.update_register_value(consts::RR2, 7).unwrap();
+
+        let outcome = vm.step().unwrap();
+
+        assert_eq!(StepOutcome::Exception(consts::EX_ACCESS_VIOLATION), outcome);
+        assert_eq!(0x1300, vm.get_register_value(consts::RPC).unwrap());
+        assert_eq!(0, vm.mem_read(0x0200).unwrap());
+    }
 }
@@ -0,0 +1,204 @@
+pub mod asm;
+pub mod errors;
+pub mod hardware;
+pub mod loader;
+pub mod os;
+pub mod symbols;
+pub mod terminal;
+
+pub use errors::VmError;
+pub use hardware::vm::{StepOutcome, TrapMode, VM};
+pub use loader::{load_image, load_images, Loader, Segment};
+pub use symbols::SymbolTable;
+
+use hardware::{consts, opcodes};
+
+use std::path::Path;
+
+/// Decodes the instruction and executes the operation it encodes
+pub fn execute_instruction(instr: u16, vm: &mut VM) -> Result<(), VmError> {
+    let op: u16 = instr >> 12;
+
+    match op {
+        opcodes::OP_ADD => {
+            opcodes::add(instr, vm)?;
+        }
+        opcodes::OP_AND => {
+            opcodes::and(instr, vm)?;
+        }
+        opcodes::OP_NOT => {
+            opcodes::not(instr, vm)?;
+        }
+        opcodes::OP_BR => {
+            opcodes::br(instr, vm)?;
+        }
+        opcodes::OP_JMP => {
+            opcodes::jmp(instr, vm)?;
+        }
+        opcodes::OP_JSR => {
+            opcodes::jsr(instr, vm)?;
+        }
+        opcodes::OP_LD => {
+            opcodes::ld(instr, vm)?;
+        }
+        opcodes::OP_LDI => {
+            opcodes::ldi(instr, vm)?;
+        }
+        opcodes::OP_LDR => {
+            opcodes::ldr(instr, vm)?;
+        }
+        opcodes::OP_LEA => {
+            opcodes::lea(instr, vm)?;
+        }
+        opcodes::OP_ST => {
+            opcodes::st(instr, vm)?;
+        }
+        opcodes::OP_STI => {
+            opcodes::sti(instr, vm)?;
+        }
+        opcodes::OP_STR => {
+            opcodes::str(instr, vm)?;
+        }
+        opcodes::OP_TRAP => {
+            opcodes::trap(instr, vm)?;
+        }
+        opcodes::OP_RTI => {
+            opcodes::rti(instr, vm)?;
+        }
+        _ => {
+            // RES should not be used
+            let pc = vm.get_register_value(consts::RPC)?.wrapping_sub(1);
+            return Err(VmError::IllegalOpcode { pc, instr });
+        }
+    }
+
+    Ok(())
+}
+
+/// Why the vm stopped executing the program
+#[derive(Debug)]
+pub enum ExitReason {
+    /// The program halted the machine
+    Halted,
+    /// The program failed with an error
+    Error(VmError),
+    /// The program executed the maximum number of instructions it was given
+    BudgetExhausted,
+    /// The pc reached the breakpoint in the given address. Executing the program again goes on from it
+    Breakpoint(u16),
+    /// The program needs a key, and the console can't wait for one. Executing the program again retries the instruction
+    WaitingForInput,
+}
+
+/// Steps through the program, starting at the current pc, until the machine stops or, if there is a limit, until that many instructions are executed
+pub fn execute_program(vm: &mut VM, limit: Option<u64>) -> ExitReason {
+    let mut executed: u64 = 0;
+    loop {
+        if limit.is_some_and(|limit| executed >= limit) {
+            return ExitReason::BudgetExhausted;
+        }
+
+        match vm.step() {
+            Ok(StepOutcome::Halted) => return ExitReason::Halted,
+            Ok(StepOutcome::Breakpoint(address)) => return ExitReason::Breakpoint(address),
+            // Waiting for a key is not an instruction executed
+            Ok(StepOutcome::WaitingForInput) => match vm.console_mut().wait_for_key() {
+                Ok(true) => {}
+                Ok(false) => return ExitReason::WaitingForInput,
+                Err(e) => return ExitReason::Error(e),
+            },
+            Ok(_) => executed += 1,
+            Err(e) => return ExitReason::Error(e),
+        }
+    }
+}
+
+/// Loads the image in the given path into a new VM and runs it from its origin until it halts
+pub fn run<P: AsRef<Path>>(path: P) -> Result<VM, VmError> {
+    let mut vm = VM::new();
+    let origin = load_image(path, &mut vm)?;
+
+    vm.update_register_value(consts::RPC, origin)?;
+    vm.update_register_value(consts::RCOND, consts::FL_ZRO)?;
+
+    match execute_program(&mut vm, None) {
+        ExitReason::Error(e) => Err(e),
+        _ => Ok(vm),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{execute_instruction, execute_program, ExitReason, VmError, VM};
+    use crate::hardware::{console::BufferConsole, consts};
+
+    #[test]
+    fn test_execute_instruction_dispatches_on_the_opcode() {
+        // The top four bits select the operation, here an ADD of RR1 and an imm5 into RR3
+        let mut vm = VM::new();
+        vm.update_register_value(consts::RR1, 3).unwrap();
+
+        execute_instruction(0b0001011001100111, &mut vm).unwrap();
+
+        assert_eq!(10, vm.get_register_value(consts::RR3).unwrap());
+    }
+
+    #[test]
+    fn test_execute_instruction_with_reserved_opcode_is_an_error() {
+        // RES is not a valid instruction, the error has the address of the instruction
+        let mut vm = VM::new();
+        vm.update_register_value(consts::RPC, 0x3001).unwrap();
+
+        let result = execute_instruction(0b1101000000000000, &mut vm);
+
+        assert!(matches!(
+            result,
+            Err(VmError::IllegalOpcode {
+                pc: 0x3000,
+                instr: 0b1101000000000000
+            })
+        ));
+    }
+
+    #[test]
+    fn test_execute_program_runs_until_halt() {
+        // 'Add RR1 and an imm5 and put the result on RR3' and then 'HALT'
+        let mut vm = VM::with_console(Box::new(BufferConsole::new(b"")));
+        vm.update_register_value(consts::RPC, 0x3000).unwrap();
+        vm.mem_write(0x3000, 0b0001011001100111).unwrap();
+        vm.mem_write(0x3001, 0xF025).unwrap();
+
+        let reason = execute_program(&mut vm, None);
+
+        assert!(matches!(reason, ExitReason::Halted));
+        assert_eq!(7, vm.get_register_value(consts::RR3).unwrap());
+    }
+
+    #[test]
+    fn test_execute_program_without_keys_gives_the_control_back() {
+        // 'GETC' with an empty console and no limit returns instead of spinning, with the pc on the GETC
+        let mut vm = VM::with_console(Box::new(BufferConsole::new(b"")));
+        vm.update_register_value(consts::RPC, 0x3000).unwrap();
+        vm.mem_write(0x3000, 0xF020).unwrap();
+
+        let reason = execute_program(&mut vm, None);
+
+        assert!(matches!(reason, ExitReason::WaitingForInput));
+        assert_eq!(0x3000, vm.get_register_value(consts::RPC).unwrap());
+    }
+
+    #[test]
+    fn test_execute_program_stops_when_the_budget_is_exhausted() {
+        // 'Branch always to itself' never ends
+        let mut vm = VM::new();
+        vm.update_register_value(consts::RPC, 0x3000).unwrap();
+        vm.update_register_value(consts::RCOND, consts::FL_ZRO)
+            .unwrap();
+        vm.mem_write(0x3000, 0b0000111111111111).unwrap();
+
+        let reason = execute_program(&mut vm, Some(100));
+
+        assert!(matches!(reason, ExitReason::BudgetExhausted));
+        assert_eq!(0x3000, vm.get_register_value(consts::RPC).unwrap());
+    }
+}
@@ -0,0 +1,300 @@
+use crate::{errors::VmError, hardware::consts, VM};
+
+use std::{
+    fs::File,
+    io::{BufReader, ErrorKind, Read},
+    path::Path,
+};
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+/// The words an image placed in memory, from its origin on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    /// Name of the image, the path for files
+    pub image: String,
+    pub origin: u16,
+    pub len: usize,
+}
+
+impl Segment {
+    /// Returns the address after the last word of the segment. It can be the end of the memory (x10000)
+    pub fn end(&self) -> usize {
+        self.origin as usize + self.len
+    }
+
+    /// Returns the first address used by both segments, if any
+    pub fn overlap(&self, other: &Segment) -> Option<u16> {
+        let start = self.origin.max(other.origin);
+        if (start as usize) < self.end().min(other.end()) {
+            Some(start)
+        } else {
+            None
+        }
+    }
+}
+
+/// Loads images in the memory of a vm, checking that each one fits in the memory and doesn't overlap the ones loaded before
+#[derive(Default)]
+pub struct Loader {
+    segments: Vec<Segment>,
+}
+
+impl Loader {
+    pub fn new() -> Self {
+        Loader {
+            segments: Vec::new(),
+        }
+    }
+
+    /// The segments loaded so far, in the order they were loaded
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Opens the image file in the given path and loads it in the vm memory
+    pub fn load_image<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        vm: &mut VM,
+    ) -> Result<&Segment, VmError> {
+        let path = path.as_ref();
+        let f = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                return Err(VmError::IncorrectFileNameError(
+                    path.display().to_string(),
+                    e,
+                ));
+            }
+        };
+
+        self.read_image(BufReader::new(f), &path.display().to_string(), vm)
+    }
+
+    /// Reads an image (a big endian origin followed by the words to place from it) and loads it in the vm memory. Nothing is loaded if the image is not valid
+    pub fn read_image<R: Read>(
+        &mut self,
+        mut reader: R,
+        image: &str,
+        vm: &mut VM,
+    ) -> Result<&Segment, VmError> {
+        let origin = match reader.read_u16::<BigEndian>() {
+            Ok(origin) => origin,
+            Err(e) => return Err(VmError::BadFileError(e)),
+        };
+
+        let mut words = Vec::new();
+        loop {
+            match reader.read_u16::<BigEndian>() {
+                Ok(word) => words.push(word),
+                Err(e) => {
+                    if e.kind() == ErrorKind::UnexpectedEof {
+                        break;
+                    }
+                    return Err(VmError::BadFileError(e)); // Could be a corrupted file
+                }
+            }
+        }
+
+        let segment = Segment {
+            image: image.to_string(),
+            origin,
+            len: words.len(),
+        };
+        if segment.end() > consts::MEMORY_MAX {
+            return Err(VmError::ImageTooLarge {
+                image: segment.image,
+                origin,
+                len: segment.len,
+            });
+        }
+        if let Some((other, address)) = self
+            .segments
+            .iter()
+            .find_map(|other| other.overlap(&segment).map(|address| (other, address)))
+        {
+            return Err(VmError::ImageOverlap {
+                image: segment.image,
+                other: other.image.clone(),
+                address,
+            });
+        }
+
+        for (address, word) in (origin..=u16::MAX).zip(words) {
+            vm.load_word(address, word);
+        }
+
+        self.segments.push(segment);
+        Ok(&self.segments[self.segments.len() - 1])
+    }
+}
+
+/// Opens the image file in the given path and loads it in the vm memory. Returns the origin of the image
+pub fn load_image<P: AsRef<Path>>(path: P, vm: &mut VM) -> Result<u16, VmError> {
+    Loader::new()
+        .load_image(path, vm)
+        .map(|segment| segment.origin)
+}
+
+/// Loads every image in the memory of the same vm, in the given order. Returns the segment of each image
+pub fn load_images<P: AsRef<Path>>(paths: &[P], vm: &mut VM) -> Result<Vec<Segment>, VmError> {
+    if paths.is_empty() {
+        return Err(VmError::NotEnoughArguments);
+    }
+
+    let mut loader = Loader::new();
+    for path in paths {
+        loader.load_image(path, vm)?;
+    }
+    Ok(loader.segments)
+}
+
+/// Reads an image (a big endian origin followed by the words to place from it) and loads it in the vm memory. Returns the origin of the image
+pub fn read_image<R: Read>(reader: R, vm: &mut VM) -> Result<u16, VmError> {
+    Loader::new()
+        .read_image(reader, "image", vm)
+        .map(|segment| segment.origin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_image, load_images, read_image, Loader, Segment};
+    use crate::{errors::VmError, VM};
+
+    #[test]
+    fn test_read_image_places_words_from_the_origin() {
+        // The first word is the origin, the rest are placed one after the other from it
+        let mut vm = VM::new();
+        let image: &[u8] = &[0x30, 0x00, 0x12, 0x34, 0xAB, 0xCD];
+
+        let origin = read_image(image, &mut vm).unwrap();
+
+        assert_eq!(0x3000, origin);
+        assert_eq!(0x1234, vm.mem_read(0x3000).unwrap());
+        assert_eq!(0xABCD, vm.mem_read(0x3001).unwrap());
+    }
+
+    #[test]
+    fn test_read_image_without_origin_is_an_error() {
+        // An image needs at least the two bytes of the origin
+        let mut vm = VM::new();
+        let image: &[u8] = &[0x30];
+
+        assert!(read_image(image, &mut vm).is_err());
+    }
+
+    #[test]
+    fn test_read_image_can_fill_the_last_address() {
+        let mut vm = VM::new();
+        let image: &[u8] = &[0xFF, 0xFE, 0x12, 0x34, 0xAB, 0xCD];
+
+        read_image(image, &mut vm).unwrap();
+
+        assert_eq!(0xABCD, vm.mem_read(0xFFFF).unwrap());
+    }
+
+    #[test]
+    fn test_read_image_past_the_end_of_memory_is_an_error() {
+        // Nothing of the image is loaded
+        let mut vm = VM::new();
+        let image: &[u8] = &[0xFF, 0xFF, 0x12, 0x34, 0xAB, 0xCD];
+
+        let result = read_image(image, &mut vm);
+
+        assert!(matches!(
+            result,
+            Err(VmError::ImageTooLarge {
+                origin: 0xFFFF,
+                len: 2,
+                ..
+            })
+        ));
+        assert_eq!(0, vm.mem_read(0xFFFF).unwrap());
+    }
+
+    #[test]
+    fn test_loader_keeps_the_segment_map() {
+        let mut vm = VM::new();
+        let mut loader = Loader::new();
+
+        loader
+            .read_image(&[0x30, 0x00, 0x00, 0x01, 0x00, 0x02][..], "a", &mut vm)
+            .unwrap();
+        loader
+            .read_image(&[0x30, 0x02, 0x00, 0x03][..], "b", &mut vm)
+            .unwrap();
+
+        assert_eq!(
+            &[
+                Segment {
+                    image: "a".to_string(),
+                    origin: 0x3000,
+                    len: 2
+                },
+                Segment {
+                    image: "b".to_string(),
+                    origin: 0x3002,
+                    len: 1
+                }
+            ],
+            loader.segments()
+        );
+    }
+
+    #[test]
+    fn test_loader_with_overlapping_images_is_an_error() {
+        // The error has the first address used by both
+        let mut vm = VM::new();
+        let mut loader = Loader::new();
+        loader
+            .read_image(&[0x30, 0x01, 0x00, 0x01, 0x00, 0x02][..], "a", &mut vm)
+            .unwrap();
+
+        let result = loader.read_image(&[0x30, 0x00, 0x00, 0x03, 0x00, 0x04][..], "b", &mut vm);
+
+        assert!(matches!(
+            result,
+            Err(VmError::ImageOverlap {
+                address: 0x3001,
+                ..
+            })
+        ));
+        assert_eq!(1, loader.segments().len());
+    }
+
+    #[test]
+    fn test_load_image_with_unexistent_file_is_an_error() {
+        let mut vm = VM::new();
+
+        assert!(load_image("examples/not-a-game.obj", &mut vm).is_err());
+    }
+
+    #[test]
+    fn test_load_images_loads_all_of_them_in_the_same_vm() {
+        let mut vm = VM::new();
+
+        let segments = load_images(&["os/lc3os.obj", "examples/hello-world.obj"], &mut vm).unwrap();
+
+        assert_eq!(0x0000, segments[0].origin);
+        assert_eq!(0x3000, segments[1].origin);
+        assert_eq!("examples/hello-world.obj", segments[1].image);
+    }
+
+    #[test]
+    fn test_load_images_with_images_at_the_same_origin_is_an_error() {
+        let mut vm = VM::new();
+
+        let result = load_images(&["examples/hello-world.obj", "examples/2048.obj"], &mut vm);
+
+        assert!(matches!(result, Err(VmError::ImageOverlap { .. })));
+    }
+
+    #[test]
+    fn test_load_images_without_images_is_an_error() {
+        let mut vm = VM::new();
+        let paths: [&str; 0] = [];
+
+        assert!(load_images(&paths, &mut vm).is_err());
+    }
+}
@@ -1,135 +1,151 @@
-pub mod errors;
-pub mod hardware;
-
-use errors::VmError;
-use hardware::opcodes;
-use hardware::vm::VM;
-use std::{env, fs::File, io::BufReader};
-
-extern crate termios;
-use termios::*;
-
-use byteorder::{BigEndian, ReadBytesExt};
-
-fn execute_instruction(instr: u16, vm: &mut VM) -> Result<(), VmError> {
-    let op: u16 = instr >> 12;
+mod cli;
+
+use cli::{resolve_address, AsmOptions, Command, RunOptions};
+use lc3_vm::{
+    asm::{assemble_file_with, preprocessor::Preprocessor},
+    errors::VmError,
+    execute_program,
+    hardware::{console::ScriptedConsole, consts},
+    os::{boot_os, OS_IMAGE},
+    symbols::load_symbols,
+    terminal::TerminalGuard,
+    ExitReason, Loader, SymbolTable, TrapMode, VM,
+};
+use std::{fs, io, process};
+use structopt::StructOpt;
+
+fn main() {
+    let result = match Command::from_args() {
+        Command::Run(options) => {
+            // The errors of the program are shown with the labels loaded in the vm
+            let mut vm = VM::new();
+            run(options, &mut vm).map_err(|e| e.describe(vm.symbols()))
+        }
+        Command::Asm(options) => asm(options).map_err(|e| e.to_string()),
+    };
 
-    match op {
-        hardware::opcodes::OP_ADD => {
-            opcodes::add(instr, vm)?;
-        }
-        hardware::opcodes::OP_AND => {
-            opcodes::and(instr, vm)?;
-        }
-        hardware::opcodes::OP_NOT => {
-            opcodes::not(instr, vm)?;
-        }
-        hardware::opcodes::OP_BR => {
-            opcodes::br(instr, vm)?;
-        }
-        hardware::opcodes::OP_JMP => {
-            opcodes::jmp(instr, vm)?;
-        }
-        hardware::opcodes::OP_JSR => {
-            opcodes::jsr(instr, vm)?;
-        }
-        hardware::opcodes::OP_LD => {
-            opcodes::ld(instr, vm)?;
-        }
-        hardware::opcodes::OP_LDI => {
-            opcodes::ldi(instr, vm)?;
-        }
-        hardware::opcodes::OP_LDR => {
-            opcodes::ldr(instr, vm)?;
-        }
-        hardware::opcodes::OP_LEA => {
-            opcodes::lea(instr, vm)?;
-        }
-        hardware::opcodes::OP_ST => {
-            opcodes::st(instr, vm)?;
-        }
-        hardware::opcodes::OP_STI => {
-            opcodes::sti(instr, vm)?;
-        }
-        hardware::opcodes::OP_STR => {
-            opcodes::str(instr, vm)?;
-        }
-        hardware::opcodes::OP_TRAP => {
-            opcodes::trap(instr, vm)?;
-        }
-        _ => {} // RTI and RES should not be used
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
     }
-
-    Ok(())
 }
 
-fn execute_program(vm: &mut VM) -> Result<(), VmError> {
-    while vm.get_register_value(hardware::consts::RPC)? < hardware::consts::MEMORY_MAX as u16 {
-        let instruction = vm.mem_read(vm.get_register_value(hardware::consts::RPC)?)?;
-
-        // Increase pc
-        let current_pc = vm.get_register_value(hardware::consts::RPC)?;
-        vm.update_register_value(hardware::consts::RPC, current_pc + 1)?;
-
-        execute_instruction(instruction, vm)?;
+fn run(options: RunOptions, vm: &mut VM) -> Result<(), VmError> {
+    if let Some(input) = &options.input {
+        let script = fs::read(input)
+            .map_err(|e| VmError::IncorrectFileNameError(input.display().to_string(), e))?;
+        vm.set_console(Box::new(ScriptedConsole::new(&script)));
     }
-    Ok(())
-}
 
-fn main() -> Result<(), VmError> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        return Err(VmError::NotEnoughArguments);
+    // The operating system goes first, so the images can't overwrite it
+    let mut loader = Loader::new();
+    if options.os {
+        loader.read_image(OS_IMAGE, "os/lc3os.obj", vm)?;
     }
+    for image in &options.images {
+        loader.load_image(image, vm)?;
+    }
+    let segments = &loader.segments()[options.os as usize..];
 
-    // Termios set up
-    let stdin = 0;
-    let termios = termios::Termios::from_fd(stdin).expect("Error initializing termios from stdin");
-
-    let mut new_termios = termios;
-    new_termios.c_iflag &= IGNBRK | BRKINT | PARMRK | ISTRIP | INLCR | IGNCR | ICRNL | IXON;
-    new_termios.c_lflag &= !(ICANON | ECHO);
-
-    tcsetattr(stdin, TCSANOW, &new_termios).expect("Error from termios when setting parameters");
-
-    // File read
-    let f = match File::open(args[1].clone()) {
-        Ok(file) => file,
-        Err(e) => {
-            return Err(VmError::IncorrectFileNameError(args[1].clone(), e));
+    // The symbol table of an image is the .sym file next to it, if there is one
+    let mut symbols = SymbolTable::new();
+    for image in &options.images {
+        let path = image.with_extension("sym");
+        if path.exists() {
+            symbols.extend(&load_symbols(path)?);
         }
+    }
+    for path in &options.symbols {
+        symbols.extend(&load_symbols(path)?);
+    }
+    vm.set_symbols(symbols);
+
+    let origin = options
+        .start_image
+        .checked_sub(1)
+        .and_then(|index| segments.get(index))
+        .map(|segment| segment.origin)
+        .ok_or(VmError::ImageIndexOutOfRange(options.start_image))?;
+    let entry = match &options.entry {
+        Some(entry) => resolve_address(entry, vm.symbols())?,
+        None => origin,
     };
-    let mut file = BufReader::new(f);
+    for breakpoint in &options.breakpoints {
+        let address = resolve_address(breakpoint, vm.symbols())?;
+        vm.add_breakpoint(address);
+    }
+    vm.update_register_value(consts::RPC, entry)?;
+    vm.update_register_value(consts::RCOND, consts::FL_ZRO)?;
 
-    let mut base_address = file
-        .read_u16::<BigEndian>()
-        .expect("Error reading the base address from the file");
+    if options.supervisor_traps {
+        vm.set_trap_mode(TrapMode::Supervisor);
+    } else if options.vectored_traps {
+        vm.set_trap_mode(TrapMode::Vectored);
+    }
+    vm.set_vectored_exceptions(options.vectored_exceptions);
+    vm.set_access_control(options.access_control);
 
-    let mut vm = VM::new();
+    if options.os {
+        boot_os(vm, entry)?;
+    }
 
-    loop {
-        match file.read_u16::<BigEndian>() {
-            Ok(instruction) => {
-                vm.mem_write(base_address, instruction);
-                base_address += 1;
-            }
-            Err(e) => {
-                if e.kind() == std::io::ErrorKind::UnexpectedEof {
-                    println!("OK")
-                } else {
-                    println!("failed: {}", e);
-                    return Err(VmError::BadFileError(e)); // Could be a corrupted file
-                }
-                break;
-            }
-        }
+    if options.trace {
+        vm.set_trace(Some(Box::new(io::stderr())));
     }
 
-    // Execute program
-    execute_program(&mut vm)?;
+    // The terminal settings are restored when the guard is dropped, whatever way this function ends
+    let _terminal = if options.no_raw_terminal {
+        TerminalGuard::disabled()
+    } else {
+        TerminalGuard::new()?
+    };
 
-    // Reset terminal settings
-    tcsetattr(stdin, TCSANOW, &termios).expect("Error from termios when reseting parameters");
+    match execute_program(vm, options.limit) {
+        ExitReason::Halted => Ok(()),
+        ExitReason::BudgetExhausted => {
+            eprintln!(
+                "Stopped after executing {} instructions",
+                options.limit.unwrap_or_default()
+            );
+            Ok(())
+        }
+        ExitReason::Breakpoint(address) => {
+            eprintln!(
+                "Stopped at the breakpoint in {}",
+                vm.symbols().describe(address)
+            );
+            eprintln!("{}", vm.dump_registers());
+            Ok(())
+        }
+        ExitReason::WaitingForInput => {
+            eprintln!("Stopped waiting for a key that will never come");
+            Ok(())
+        }
+        ExitReason::Error(e) => Err(e),
+    }
+}
 
+fn asm(options: AsmOptions) -> Result<(), VmError> {
+    let mut preprocessor = Preprocessor::new();
+    for (name, value) in &options.defines {
+        preprocessor.define(name, value);
+    }
+    let program = assemble_file_with(&options.source, preprocessor)?;
+
+    // The symbol table goes next to the object image
+    let output = options
+        .output
+        .unwrap_or_else(|| options.source.with_extension("obj"));
+    let sym = output.with_extension("sym");
+    fs::write(&output, program.to_image())
+        .map_err(|e| VmError::OutputFileError(output.display().to_string(), e))?;
+    fs::write(&sym, program.symbols.to_sym())
+        .map_err(|e| VmError::OutputFileError(sym.display().to_string(), e))?;
+
+    if options.listing {
+        let lst = output.with_extension("lst");
+        fs::write(&lst, program.listing())
+            .map_err(|e| VmError::OutputFileError(lst.display().to_string(), e))?;
+    }
     Ok(())
 }
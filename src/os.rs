@@ -0,0 +1,153 @@
+use crate::{errors::VmError, hardware::consts, loader::read_image, TrapMode, VM};
+
+/// The operating system shipped with the vm, assembled from os/lc3os.asm
+pub const OS_IMAGE: &[u8] = include_bytes!("../os/lc3os.obj");
+
+/// Where the startup code takes the address of the user program from
+pub const OS_USER_PC: u16 = 0x0200;
+/// The first instruction of the startup code
+pub const OS_START: u16 = 0x0201;
+
+/// Loads the operating system and leaves the vm ready to boot it in supervisor mode. The startup code jumps to the given address in user mode
+pub fn load_os(vm: &mut VM, user_pc: u16) -> Result<(), VmError> {
+    read_image(OS_IMAGE, vm)?;
+    boot_os(vm, user_pc)
+}
+
+/// Leaves the vm ready to boot an operating system already loaded, which jumps to the given address in user mode
+pub fn boot_os(vm: &mut VM, user_pc: u16) -> Result<(), VmError> {
+    vm.load_word(OS_USER_PC, user_pc);
+
+    // Traps and exceptions are served by the routines of the os
+    vm.set_trap_mode(TrapMode::Supervisor);
+    vm.set_vectored_exceptions(true);
+
+    // The user stack starts below the device registers
+    vm.set_psr(vm.get_psr() & !consts::PSR_USER);
+    vm.set_saved_usp(consts::DEVICE_SPACE_START);
+    vm.update_register_value(consts::RPC, OS_START)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_os, OS_START};
+    use crate::{
+        execute_program,
+        hardware::{console::BufferConsole, consts},
+        load_image, ExitReason, StepOutcome, VM,
+    };
+
+    /// Steps until the pc reaches the given address, at most a thousand times
+    fn run_until(vm: &mut VM, address: u16) {
+        for _ in 0..1000 {
+            if vm.get_register_value(consts::RPC).unwrap() == address {
+                return;
+            }
+            vm.step().unwrap();
+        }
+        panic!("x{:04X} was never reached", address);
+    }
+
+    #[test]
+    fn test_load_os_boots_in_supervisor_mode() {
+        let mut vm = VM::with_console(Box::new(BufferConsole::new(b"")));
+
+        load_os(&mut vm, consts::PC_START).unwrap();
+
+        assert!(!vm.is_user_mode());
+        assert_eq!(OS_START, vm.get_register_value(consts::RPC).unwrap());
+    }
+
+    #[test]
+    fn test_os_startup_jumps_to_the_user_program_in_user_mode() {
+        // The user stack is left below the device registers
+        let mut vm = VM::with_console(Box::new(BufferConsole::new(b"")));
+        load_os(&mut vm, 0x3100).unwrap();
+
+        run_until(&mut vm, 0x3100);
+
+        assert!(vm.is_user_mode());
+        assert_eq!(
+            consts::DEVICE_SPACE_START,
+            vm.get_register_value(consts::RR6).unwrap()
+        );
+        assert_eq!(consts::SSP_START, vm.saved_ssp());
+    }
+
+    #[test]
+    fn test_os_getc_reads_the_keyboard_and_returns_to_the_user_program() {
+        // 'GETC' served by the os routine, which polls KBSR and reads KBDR
+        let mut vm = VM::with_console(Box::new(BufferConsole::new(b"k")));
+        load_os(&mut vm, consts::PC_START).unwrap();
+        vm.load_word(0x3000, 0xF020);
+
+        run_until(&mut vm, 0x3000);
+        assert!(matches!(vm.step(), Ok(StepOutcome::Trap(0x20))));
+        run_until(&mut vm, 0x3001);
+
+        assert!(vm.is_user_mode());
+        assert_eq!(b'k' as u16, vm.get_register_value(consts::RR0).unwrap());
+        assert_eq!(0x3001, vm.get_register_value(consts::RR7).unwrap());
+    }
+
+    #[test]
+    fn test_os_puts_writes_the_string_through_the_display() {
+        // 'LEA RR0 at pc+2', 'PUTS' and the string
+        let console = BufferConsole::new(b"");
+        let output = console.output();
+        let mut vm = VM::with_console(Box::new(console));
+        load_os(&mut vm, consts::PC_START).unwrap();
+        vm.load_word(0x3000, 0b1110000000000001);
+        vm.load_word(0x3001, 0xF022);
+        vm.load_word(0x3002, b'o' as u16);
+        vm.load_word(0x3003, b'k' as u16);
+
+        run_until(&mut vm, 0x3002);
+
+        assert_eq!(b"ok".to_vec(), *output.borrow());
+        assert_eq!(0x3002, vm.get_register_value(consts::RR0).unwrap());
+    }
+
+    #[test]
+    fn test_os_halt_stops_the_machine() {
+        // The halt routine writes its message and clears the clock enable bit of the MCR
+        let console = BufferConsole::new(b"");
+        let output = console.output();
+        let mut vm = VM::with_console(Box::new(console));
+        load_os(&mut vm, consts::PC_START).unwrap();
+        vm.load_word(0x3000, 0xF025);
+
+        let reason = execute_program(&mut vm, Some(10_000));
+
+        assert!(matches!(reason, ExitReason::Halted));
+        assert_eq!(b"HALT detected\n".to_vec(), *output.borrow());
+    }
+
+    #[test]
+    fn test_os_runs_a_program_until_it_halts() {
+        // The hello world example, started by the startup code of the os, prints through the display and halts with the MCR
+        let console = BufferConsole::new(b"");
+        let output = console.output();
+        let mut vm = VM::with_console(Box::new(console));
+        let origin = load_image("examples/hello-world.obj", &mut vm).unwrap();
+        load_os(&mut vm, origin).unwrap();
+
+        let reason = execute_program(&mut vm, Some(100_000));
+
+        assert!(matches!(reason, ExitReason::Halted));
+        assert_eq!(b"Hello World!HALT detected\n".to_vec(), *output.borrow());
+    }
+
+    #[test]
+    fn test_os_handles_illegal_opcodes() {
+        // A RES in the user program goes to the exception handler of the os, in supervisor mode
+        let mut vm = VM::with_console(Box::new(BufferConsole::new(b"")));
+        load_os(&mut vm, consts::PC_START).unwrap();
+        vm.load_word(0x3000, 0xD000);
+
+        run_until(&mut vm, 0x3000);
+
+        assert!(matches!(vm.step(), Ok(StepOutcome::Exception(0x01))));
+        assert!(!vm.is_user_mode());
+    }
+}
@@ -0,0 +1,143 @@
+use crate::errors::VmError;
+
+use std::{fs, path::Path};
+
+/// The labels of a program and their addresses, in the order they were defined
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    symbols: Vec<(String, u16)>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable {
+            symbols: Vec::new(),
+        }
+    }
+
+    pub fn insert(&mut self, label: &str, address: u16) {
+        self.symbols.push((label.to_string(), address));
+    }
+
+    /// Adds the symbols of another table, for example the one of another image
+    pub fn extend(&mut self, other: &SymbolTable) {
+        self.symbols.extend(other.symbols.iter().cloned());
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> {
+        self.symbols
+            .iter()
+            .map(|(label, address)| (label.as_str(), *address))
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn address(&self, label: &str) -> Option<u16> {
+        self.iter()
+            .find(|(defined, _)| *defined == label)
+            .map(|(_, address)| address)
+    }
+
+    /// Returns the label at the address or, if there is none, the closest one before it, with the distance to the address
+    pub fn locate(&self, address: u16) -> Option<(&str, u16)> {
+        self.iter()
+            .filter(|(_, defined)| *defined <= address)
+            .max_by_key(|(_, defined)| *defined)
+            .map(|(label, defined)| (label, address - defined))
+    }
+
+    /// Formats the address with the label it's in, like x3004 (LOOP+4)
+    pub fn describe(&self, address: u16) -> String {
+        match self.locate(address) {
+            Some((label, 0)) => format!("x{:04X} ({})", address, label),
+            Some((label, distance)) => format!("x{:04X} ({}+{})", address, label, distance),
+            None => format!("x{:04X}", address),
+        }
+    }
+
+    /// Reads a symbol table in the format of the .sym files. The lines that are not a label and an address are skipped
+    pub fn parse(text: &str) -> Self {
+        let mut table = SymbolTable::new();
+        for line in text.lines() {
+            let line = line.trim_start().trim_start_matches('/');
+            let mut words = line.split_whitespace();
+            if let (Some(label), Some(address), None) = (words.next(), words.next(), words.next()) {
+                let hex = address.trim_start_matches(['x', 'X']);
+                if let Ok(address) = u16::from_str_radix(hex, 16) {
+                    table.insert(label, address);
+                }
+            }
+        }
+        table
+    }
+
+    /// Writes the table in the format of the .sym files
+    pub fn to_sym(&self) -> String {
+        let mut text = String::from(
+            "// Symbol table\n// Scope level 0:\n//\tSymbol Name       Page Address\n//\t----------------  ------------\n",
+        );
+        for (label, address) in self.iter() {
+            text.push_str(&format!("//\t{:<16}  {:04X}\n", label, address));
+        }
+        text
+    }
+}
+
+/// Reads the .sym file in the given path
+pub fn load_symbols<P: AsRef<Path>>(path: P) -> Result<SymbolTable, VmError> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)
+        .map_err(|e| VmError::IncorrectFileNameError(path.display().to_string(), e))?;
+
+    Ok(SymbolTable::parse(&text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SymbolTable;
+
+    fn table() -> SymbolTable {
+        let mut table = SymbolTable::new();
+        table.insert("MAIN", 0x3000);
+        table.insert("LOOP", 0x3004);
+        table
+    }
+
+    #[test]
+    fn test_address_of_a_label() {
+        assert_eq!(Some(0x3004), table().address("LOOP"));
+        assert_eq!(None, table().address("loop"));
+    }
+
+    #[test]
+    fn test_describe_uses_the_closest_label_before_the_address() {
+        let table = table();
+
+        assert_eq!("x3004 (LOOP)", table.describe(0x3004));
+        assert_eq!("x3002 (MAIN+2)", table.describe(0x3002));
+        assert_eq!("x2FFF", table.describe(0x2FFF));
+    }
+
+    #[test]
+    fn test_sym_format_can_be_read_back() {
+        let table = table();
+
+        assert_eq!(table, SymbolTable::parse(&table.to_sym()));
+    }
+
+    #[test]
+    fn test_parse_skips_the_header() {
+        let text = "// Symbol table\n// Scope level 0:\n//\tSymbol Name       Page Address\n//\t----------------  ------------\n//\tSTART             3000\n";
+
+        let table = SymbolTable::parse(text);
+
+        assert_eq!(1, table.len());
+        assert_eq!(Some(0x3000), table.address("START"));
+    }
+}
@@ -0,0 +1,112 @@
+use crate::errors::VmError;
+
+use std::{os::unix::io::RawFd, sync::OnceLock};
+
+use termios::{
+    tcsetattr, Termios, BRKINT, ECHO, ICANON, ICRNL, IGNBRK, IGNCR, INLCR, ISTRIP, IXON, PARMRK,
+    TCSANOW,
+};
+
+/// The terminal and the settings to put back when a signal ends the process. They are kept apart since the signal handler can't reach the guard
+static ORIGINAL_SETTINGS: OnceLock<(RawFd, Termios)> = OnceLock::new();
+
+/// Puts the terminal in raw mode, and restores the original settings when it is dropped
+pub struct TerminalGuard {
+    fd: RawFd,
+    original: Option<Termios>,
+}
+
+impl TerminalGuard {
+    /// Disables the canonical mode and the echo of the terminal in the stdin. If the stdin is a pipe or a file, it's left untouched
+    pub fn new() -> Result<Self, VmError> {
+        Self::for_fd(libc::STDIN_FILENO)
+    }
+
+    /// Like new, for the terminal in the given file descriptor
+    pub fn for_fd(fd: RawFd) -> Result<Self, VmError> {
+        if unsafe { libc::isatty(fd) } == 0 {
+            return Ok(TerminalGuard { fd, original: None });
+        }
+
+        let original = Termios::from_fd(fd).map_err(VmError::TerminalError)?;
+
+        let mut raw = original;
+        raw.c_iflag &= IGNBRK | BRKINT | PARMRK | ISTRIP | INLCR | IGNCR | ICRNL | IXON;
+        raw.c_lflag &= !(ICANON | ECHO);
+
+        let _ = ORIGINAL_SETTINGS.set((fd, original));
+        install_signal_handlers();
+
+        tcsetattr(fd, TCSANOW, &raw).map_err(VmError::TerminalError)?;
+
+        Ok(TerminalGuard {
+            fd,
+            original: Some(original),
+        })
+    }
+
+    /// Returns a guard that doesn't change the terminal
+    pub fn disabled() -> Self {
+        TerminalGuard {
+            fd: libc::STDIN_FILENO,
+            original: None,
+        }
+    }
+
+    pub fn is_raw(&self) -> bool {
+        self.original.is_some()
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        if let Some(original) = &self.original {
+            // There is nothing left to do if the terminal can't be restored
+            let _ = tcsetattr(self.fd, TCSANOW, original);
+        }
+    }
+}
+
+/// Restores the terminal settings and ends the process, as the signal would have done
+extern "C" fn restore_and_exit(signal: libc::c_int) {
+    if let Some((fd, original)) = ORIGINAL_SETTINGS.get() {
+        let _ = tcsetattr(*fd, TCSANOW, original);
+    }
+    unsafe { libc::_exit(128 + signal) };
+}
+
+fn install_signal_handlers() {
+    let handler = restore_and_exit as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        unsafe {
+            libc::signal(signal, handler);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TerminalGuard;
+
+    #[test]
+    fn test_guard_leaves_a_pipe_untouched() {
+        // A pipe is not a terminal, so there are no settings to change or to restore
+        let mut fds = [0; 2];
+        assert_eq!(0, unsafe { libc::pipe(fds.as_mut_ptr()) });
+
+        let guard = TerminalGuard::for_fd(fds[0]).unwrap();
+        let raw = guard.is_raw();
+        drop(guard);
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+
+        assert!(!raw);
+    }
+
+    #[test]
+    fn test_disabled_guard_is_not_raw() {
+        assert!(!TerminalGuard::disabled().is_raw());
+    }
+}